@@ -1,11 +1,38 @@
-use crate::lease::Lease;
+// lints of the original code of Auth, kept as is
+#![allow(clippy::bind_instead_of_map, clippy::bool_assert_comparison)]
 
-use std::time::Duration;
+use crate::{
+	lease::{Lease, RenewPolicy},
+	redact::Token,
+};
+
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{collections::HashMap, time::Duration};
+
+/// Type of a vault token
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenType {
+	/// persisted token that can be renewed, revoked and used to create child tokens
+	#[default]
+	Service,
+	/// lightweight token that can't be renewed nor revoked and expires at the end of its ttl
+	Batch,
+}
 
 /// tie an auth token to a lease
 #[derive(Debug)]
 pub struct Auth {
-	pub client_token: String,
+	pub client_token: Token,
+	pub accessor: String,
+	pub policies: Vec<String>,
+	pub token_policies: Vec<String>,
+	pub metadata: HashMap<String, String>,
+	pub entity_id: String,
+	pub orphan: bool,
+	pub renewable: bool,
+	pub token_type: TokenType,
+	/// ttl of the token (none if the token never expires)
 	pub lease: Option<Lease>,
 }
 
@@ -13,7 +40,15 @@ impl Auth {
 	/// create new Auth with an optional duration
 	pub fn new(token: &str, dur: Option<Duration>) -> Self {
 		Self {
-			client_token: token.to_owned(),
+			client_token: Token::new(token),
+			accessor: String::new(),
+			policies: Vec::new(),
+			token_policies: Vec::new(),
+			metadata: HashMap::new(),
+			entity_id: String::new(),
+			orphan: false,
+			renewable: dur.is_some(),
+			token_type: TokenType::Service,
 			lease: dur.and_then(|dur| Some(Lease::new(dur))),
 		}
 	}
@@ -24,11 +59,16 @@ impl Auth {
 			|| self.lease.as_ref().filter(|l| l.is_valid()).is_some()
 	}
 
-	/// check if the token needs a renewal
+	/// check if the token needs a renewal (or a new login if it is not renewable)
 	pub fn to_renew(&self) -> bool {
 		self.lease.as_ref().filter(|l| l.to_renew()).is_some()
 	}
 
+	/// check if the token can be extended with `auth/token/renew-self`
+	pub fn is_renewable(&self) -> bool {
+		self.renewable && self.token_type == TokenType::Service
+	}
+
 	pub fn duration(&self) -> Option<Duration> {
 		self.lease.as_ref().and_then(|l| Some(l.lease_duration))
 	}
@@ -43,3 +83,158 @@ fn empty_token_is_invalid() {
 	let auth = Auth::new("", None);
 	assert_eq!(auth.is_valid(), false);
 }
+
+/// auth block of a login or token creation response
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct AuthBlock {
+	client_token: Token,
+	accessor: String,
+	#[serde(deserialize_with = "null_default")]
+	policies: Vec<String>,
+	#[serde(deserialize_with = "null_default")]
+	token_policies: Vec<String>,
+	#[serde(deserialize_with = "null_default")]
+	metadata: HashMap<String, String>,
+	lease_duration: u64,
+	renewable: bool,
+	entity_id: String,
+	orphan: bool,
+	token_type: TokenType,
+}
+
+/// response of a login or token creation
+#[derive(Debug, Deserialize)]
+pub(crate) struct AuthResponse {
+	pub auth: AuthBlock,
+}
+
+impl AuthBlock {
+	/// convert to an Auth which lease is renewed according to policy
+	pub fn into_auth(self, policy: &RenewPolicy) -> Auth {
+		// a token without ttl never expires
+		let lease = if self.lease_duration != 0 {
+			Some(Lease::with_policy(
+				Duration::from_secs(self.lease_duration),
+				policy,
+			))
+		} else {
+			None
+		};
+		Auth {
+			client_token: self.client_token,
+			accessor: self.accessor,
+			policies: self.policies,
+			token_policies: self.token_policies,
+			metadata: self.metadata,
+			entity_id: self.entity_id,
+			orphan: self.orphan,
+			renewable: self.renewable,
+			token_type: self.token_type,
+			lease,
+		}
+	}
+}
+
+/// Properties of a token as returned by `auth/token/lookup-self`
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TokenInfo {
+	pub accessor: String,
+	pub creation_time: u64,
+	pub creation_ttl: u64,
+	pub display_name: String,
+	pub entity_id: String,
+	pub expire_time: Option<String>,
+	pub explicit_max_ttl: u64,
+	pub issue_time: Option<String>,
+	#[serde(deserialize_with = "null_default")]
+	pub meta: HashMap<String, String>,
+	pub num_uses: u64,
+	pub orphan: bool,
+	pub path: String,
+	#[serde(deserialize_with = "null_default")]
+	pub policies: Vec<String>,
+	pub renewable: bool,
+	pub ttl: u64,
+	#[serde(rename = "type")]
+	pub token_type: String,
+}
+
+/// Parameters of a child or orphan token creation
+#[derive(Debug, Default, Clone)]
+pub struct TokenRequest {
+	/// policies of the token (a subset of the parent policies unless using a root token)
+	pub policies: Vec<String>,
+	/// metadata attached to the token
+	pub meta: HashMap<String, String>,
+	/// initial ttl of the token
+	pub ttl: Option<Duration>,
+	/// maximum ttl of the token, whatever the renewals
+	pub explicit_max_ttl: Option<Duration>,
+	/// name displayed in audit logs
+	pub display_name: Option<String>,
+	/// maximum number of uses of the token (0 is unlimited)
+	pub num_uses: u64,
+	/// allow the token to be renewed
+	pub renewable: Option<bool>,
+	/// create a token without parent using `auth/token/create-orphan`
+	pub orphan: bool,
+}
+
+/// deserialize a null value as the default value of the type
+fn null_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+	D: Deserializer<'de>,
+	T: Default + Deserialize<'de>,
+{
+	Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn parse_auth_block() {
+		let res: AuthResponse = serde_json::from_str(
+			r#"{"auth": {
+				"client_token": "s.token",
+				"accessor": "accessor",
+				"policies": ["default", "app"],
+				"token_policies": ["default", "app"],
+				"metadata": null,
+				"lease_duration": 3600,
+				"renewable": true,
+				"entity_id": "entity",
+				"token_type": "service",
+				"orphan": true
+			}}"#,
+		)
+		.unwrap();
+		let auth = res.auth.into_auth(&RenewPolicy::default());
+		assert_eq!(auth.client_token.expose(), "s.token");
+		assert_eq!(auth.policies, vec!["default", "app"]);
+		assert!(auth.metadata.is_empty());
+		assert!(auth.orphan);
+		assert_eq!(auth.duration(), Some(Duration::from_secs(3600)));
+	}
+
+	#[test]
+	fn batch_token_has_a_lease() {
+		let res: AuthResponse = serde_json::from_str(
+			r#"{"auth": {
+				"client_token": "b.token",
+				"lease_duration": 600,
+				"renewable": false,
+				"token_type": "batch"
+			}}"#,
+		)
+		.unwrap();
+		let auth = res.auth.into_auth(&RenewPolicy::default());
+		assert_eq!(auth.token_type, TokenType::Batch);
+		assert!(!auth.is_renewable());
+		assert!(auth.is_valid());
+		assert_eq!(auth.duration(), Some(Duration::from_secs(600)));
+	}
+}
@@ -0,0 +1,77 @@
+use argh::FromArgs;
+
+/// Fetch secrets from vault using jwt authentication
+#[derive(FromArgs)]
+pub struct Args {
+	/// vault address, or comma separated addresses of a cluster (http://127.0.0.1:8200/v1)
+	#[argh(
+		option,
+		short = 'u',
+		default = "\"http://127.0.0.1:8200/v1\".to_owned()"
+	)]
+	pub url: String,
+
+	/// login path (/auth/jwt/login)
+	#[argh(option, short = 'l', default = "\"/auth/jwt/login\".to_owned()")]
+	pub login_path: String,
+
+	/// path of the ca certificate used to verify the vault server certificate
+	#[argh(option, short = 'c')]
+	pub cacert: Option<String>,
+
+	/// path of a file containing the jwt ("-" for stdin)
+	#[argh(option, short = 'j')]
+	pub jwt_file: Option<String>,
+
+	/// name of an environment variable containing the jwt
+	#[argh(option, short = 'e')]
+	pub jwt_env: Option<String>,
+
+	#[argh(subcommand)]
+	pub cmd: SubCommand,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+pub enum SubCommand {
+	Get(Get),
+	Mounts(Mounts),
+	Login(Login),
+	Health(Health),
+}
+
+/// Fetch secrets and print their values (one per line)
+#[derive(FromArgs)]
+#[argh(subcommand, name = "get")]
+pub struct Get {
+	/// secret paths (vault:role[,method][,key=value]*:path[#anchor])
+	#[argh(positional)]
+	pub paths: Vec<String>,
+}
+
+/// Print the mount points and their backend types
+#[derive(FromArgs)]
+#[argh(subcommand, name = "mounts")]
+pub struct Mounts {
+	/// role used to log in
+	#[argh(positional)]
+	pub role: String,
+}
+
+/// Log in and print the properties of the token
+#[derive(FromArgs)]
+#[argh(subcommand, name = "login")]
+pub struct Login {
+	/// role used to log in
+	#[argh(positional)]
+	pub role: String,
+}
+
+/// Print the health of the vault server (exit with an error if it's not ready)
+#[derive(FromArgs)]
+#[argh(subcommand, name = "health")]
+pub struct Health {
+	/// wait up to the given number of seconds for the server to be ready
+	#[argh(option, short = 'w', default = "0")]
+	pub wait: u64,
+}
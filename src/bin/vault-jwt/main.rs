@@ -0,0 +1,100 @@
+mod args;
+
+use crate::args::{Args, SubCommand};
+
+use std::{
+	convert::TryFrom,
+	error::Error as _,
+	io::{self, Read},
+	path::PathBuf,
+	process,
+	time::Duration,
+};
+use vault_jwt::{
+	client::VaultClient,
+	error::{Error, Result},
+	jwt::JwtSource,
+	redact::Jwt,
+	secret::{value_to_string, Backend, SecretPath},
+};
+
+/// Return the source of the jwt: a file, an environment variable or stdin
+fn jwt_source(args: &Args) -> Result<JwtSource> {
+	Ok(match (&args.jwt_file, &args.jwt_env) {
+		(Some(path), _) if path != "-" => JwtSource::File(PathBuf::from(path)),
+		(None, Some(var)) => JwtSource::Env(var.to_owned()),
+		_ => {
+			// stdin can only be read once
+			let mut jwt = String::new();
+			io::stdin()
+				.read_to_string(&mut jwt)
+				.map_err(|e| Error::JwtSourceError {
+					src: "stdin".to_owned(),
+					source: e,
+				})?;
+			JwtSource::Static(Jwt::new(jwt))
+		}
+	})
+}
+
+/// Print a value as pretty json
+fn print_json<T: serde::Serialize>(value: &T) -> Result<()> {
+	println!(
+		"{}",
+		serde_json::to_string_pretty(value).map_err(|e| Error::ParseError { source: e })?
+	);
+	Ok(())
+}
+
+fn run(args: Args) -> Result<()> {
+	let mut client = VaultClient::new(&args.url, &args.login_path, "", args.cacert.as_deref())?;
+	if !matches!(args.cmd, SubCommand::Health(_)) {
+		client.set_jwt_source(jwt_source(&args)?);
+	}
+	match args.cmd {
+		SubCommand::Get(ref get) => {
+			for path in get.paths.iter() {
+				let path = SecretPath::<Backend>::try_from(path.as_str())?;
+				println!("{}", value_to_string(client.resolve(&path)?.expose()));
+			}
+		}
+		SubCommand::Mounts(ref mounts) => {
+			client.login(&mounts.role)?;
+			let mut mounts: Vec<(String, String)> =
+				client.get_mounts(&mounts.role)?.into_iter().collect();
+			mounts.sort();
+			for (mount, backend) in mounts.iter() {
+				println!("{} {}", mount, backend);
+			}
+		}
+		SubCommand::Login(ref login) => {
+			client.login(&login.role)?;
+			print_json(&client.lookup_self(&login.role)?)?;
+		}
+		SubCommand::Health(ref health) => {
+			let health = if health.wait != 0 {
+				client.wait_until_ready(Duration::from_secs(health.wait))?
+			} else {
+				client.health()?
+			};
+			print_json(&health)?;
+			if !health.is_ready() {
+				process::exit(2);
+			}
+		}
+	}
+	Ok(())
+}
+
+fn main() {
+	let args: Args = argh::from_env();
+	if let Err(e) = run(args) {
+		eprintln!("{}", e);
+		let mut source = e.source();
+		while let Some(e) = source {
+			eprintln!("  caused by: {}", e);
+			source = e.source();
+		}
+		process::exit(1);
+	}
+}
@@ -0,0 +1,189 @@
+use crate::secret::Secret;
+
+use std::collections::HashMap;
+
+/// Identify a secret request
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+	pub role: String,
+	pub method: String,
+	pub path: String,
+	pub kwargs: Vec<(String, String)>,
+}
+
+impl CacheKey {
+	/// create a key from the arguments of a secret request. The method is case insensitive and
+	/// the order of kwargs doesn't matter
+	pub fn new(role: &str, method: &str, path: &str, kwargs: Option<&Vec<(&str, &str)>>) -> Self {
+		let mut kwargs: Vec<(String, String)> = kwargs
+			.map(|kwargs| {
+				kwargs
+					.iter()
+					.map(|(k, v)| ((*k).to_owned(), (*v).to_owned()))
+					.collect()
+			})
+			.unwrap_or_default();
+		kwargs.sort();
+		Self {
+			role: role.to_owned(),
+			method: method.to_ascii_uppercase(),
+			path: path.to_owned(),
+			kwargs,
+		}
+	}
+}
+
+/// Statistics of a secret cache
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+	/// number of secrets served from the cache
+	pub hits: u64,
+	/// number of secrets not found (or expired) in the cache
+	pub misses: u64,
+	/// number of expired secrets removed from the cache
+	pub evictions: u64,
+	/// number of secrets currently in the cache
+	pub entries: usize,
+}
+
+/// Cache of secrets which are served as long as their lease is valid
+#[derive(Debug, Default)]
+pub struct SecretCache {
+	entries: HashMap<CacheKey, Secret>,
+	/// also cache requests with non idempotent methods (like `POST pki/issue`)
+	cache_non_idempotent: bool,
+	stats: CacheStats,
+}
+
+impl SecretCache {
+	/// create an empty cache which only caches idempotent requests unless cache_non_idempotent
+	/// is true
+	pub fn new(cache_non_idempotent: bool) -> Self {
+		Self {
+			cache_non_idempotent,
+			..Default::default()
+		}
+	}
+
+	/// check if the result of a request with the given method can be cached
+	pub fn is_cacheable(&self, method: &str) -> bool {
+		self.cache_non_idempotent || matches!(method.to_ascii_uppercase().as_str(), "GET" | "LIST")
+	}
+
+	/// return a copy of a valid cached secret or evict it if it's expired
+	pub fn get(&mut self, key: &CacheKey) -> Option<Secret> {
+		match self.entries.get(key) {
+			Some(secret) if secret.is_valid() => {
+				self.stats.hits += 1;
+				Some(secret.clone())
+			}
+			Some(_) => {
+				self.entries.remove(key);
+				self.stats.evictions += 1;
+				self.stats.misses += 1;
+				None
+			}
+			None => {
+				self.stats.misses += 1;
+				None
+			}
+		}
+	}
+
+	/// cache a copy of a secret
+	pub fn insert(&mut self, key: CacheKey, secret: &Secret) {
+		self.entries.insert(key, secret.clone());
+	}
+
+	/// evict all expired secrets
+	pub fn purge(&mut self) {
+		let len = self.entries.len();
+		self.entries.retain(|_, secret| secret.is_valid());
+		self.stats.evictions += (len - self.entries.len()) as u64;
+	}
+
+	/// remove the secrets fetched with the token of role
+	pub fn remove_role(&mut self, role: &str) {
+		self.entries.retain(|key, _| key.role != role);
+	}
+
+	/// remove all secrets
+	pub fn clear(&mut self) {
+		self.entries.clear();
+	}
+
+	/// return the statistics of the cache
+	pub fn stats(&self) -> CacheStats {
+		CacheStats {
+			entries: self.entries.len(),
+			..self.stats
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use serde_json::Value;
+	use std::time::Duration;
+
+	fn key(method: &str) -> CacheKey {
+		CacheKey::new("role", method, "secret/app", None)
+	}
+
+	#[test]
+	fn kwargs_order_is_ignored() {
+		assert_eq!(
+			CacheKey::new(
+				"role",
+				"post",
+				"pki/issue/app",
+				Some(&vec![("a", "1"), ("b", "2")])
+			),
+			CacheKey::new(
+				"role",
+				"POST",
+				"pki/issue/app",
+				Some(&vec![("b", "2"), ("a", "1")])
+			)
+		);
+	}
+
+	#[test]
+	fn post_is_not_cacheable_by_default() {
+		assert!(SecretCache::new(false).is_cacheable("get"));
+		assert!(!SecretCache::new(false).is_cacheable("POST"));
+		assert!(SecretCache::new(true).is_cacheable("POST"));
+	}
+
+	#[test]
+	fn valid_secret_is_served() {
+		let mut cache = SecretCache::new(false);
+		let secret = Secret::new(Value::String("secret".to_owned()), None);
+		assert!(cache.get(&key("GET")).is_none());
+		cache.insert(key("GET"), &secret);
+		assert_eq!(cache.get(&key("GET")), Some(secret));
+		assert_eq!(
+			cache.stats(),
+			CacheStats {
+				hits: 1,
+				misses: 1,
+				evictions: 0,
+				entries: 1
+			}
+		);
+	}
+
+	#[test]
+	fn expired_secret_is_evicted() {
+		let mut cache = SecretCache::new(false);
+		let secret = Secret::new(
+			Value::String("secret".to_owned()),
+			Some(Duration::from_secs(0)),
+		);
+		cache.insert(key("GET"), &secret);
+		assert!(cache.get(&key("GET")).is_none());
+		assert_eq!(cache.stats().evictions, 1);
+		assert_eq!(cache.stats().entries, 0);
+	}
+}
@@ -1,42 +1,147 @@
+#[cfg(all(unix, feature = "token-store"))]
+use crate::store::TokenStore;
+#[cfg(feature = "tracing")]
+use crate::trace::{self, TraceContext};
 use crate::{
-	auth::Auth,
+	auth::{Auth, AuthResponse, TokenInfo, TokenRequest, TokenType},
+	cache::{CacheKey, CacheStats, SecretCache},
 	error::{Error, Result, VaultErrors},
-	secret::Secret,
+	jwt::{Claims, JwtSource},
+	lease::RenewPolicy,
+	metrics::{self, Metrics, MetricsSnapshot},
+	middleware::{Exchange, Middleware, Middlewares},
+	redact::{Jwt, SecretValue},
+	secret::{Backend, Secret, SecretPath},
+	sys::{is_allowed, to_capabilities, Health, Leader, SealStatus, HEALTH_PATH},
 };
 
+use futures_util::stream::{self, StreamExt};
 use isahc::{
-	config::{CaCertificate, Configurable},
-	http::{Request, StatusCode},
-	AsyncReadResponseExt, HttpClient, ReadResponseExt,
+	config::{CaCertificate, Configurable, RedirectPolicy},
+	http::{header::HeaderValue, request::Builder, Method, Request, StatusCode, Uri},
+	AsyncBody, AsyncReadResponseExt, Body, HttpClient, ReadResponseExt, Response,
 };
-use serde_json::{Map, Value, json};
-use std::{collections::HashMap, time::Duration};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::{
+	collections::HashMap,
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		Arc, Mutex,
+	},
+	thread,
+	time::{Duration, Instant},
+};
+
+/// Delay between two health checks when waiting for the vault server
+const READY_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Maximum number of redirections followed (standby nodes redirect to the active node)
+const MAX_REDIRECTS: u32 = 5;
+/// Scheme of the addresses of nodes (or agents) listening on a unix socket
+const UNIX_SCHEME: &str = "unix://";
+/// Path of the api behind a unix socket
+const UNIX_API_PATH: &str = "/v1";
 
 /// Vault client that cache its auth tokens
 #[derive(Debug)]
 pub struct VaultClient {
+	/// address given to `new` (see `url()` for the address of the node requests are sent to)
 	pub url: String,
+	/// addresses of the nodes of the vault cluster
+	urls: Vec<String>,
+	/// index of the node requests are sent to
+	active: AtomicUsize,
 	login_path: String,
-	jwt: String,
+	jwt: JwtSource,
 	client: HttpClient,
 	/// map a role to an authentification token
 	pub auth: HashMap<String, Auth>,
+	/// revoke all cached tokens when the client is dropped
+	revoke_on_drop: bool,
+	/// optional cache of secrets
+	cache: Option<Mutex<SecretCache>>,
+	/// when to renew tokens and secrets
+	renew_policy: RenewPolicy,
+	/// counters and histograms of requests, logins and renewals
+	metrics: Metrics,
+	/// hooks run around every request
+	middlewares: Middlewares,
+	/// let a vault agent inject the token of requests made without login
+	agent_mode: bool,
+	/// tokens persisted across restarts
+	#[cfg(all(unix, feature = "token-store"))]
+	token_store: Option<TokenStore>,
+	/// roles which token was loaded from the store and not yet checked against vault
+	#[cfg(all(unix, feature = "token-store"))]
+	unverified: std::collections::HashSet<String>,
+	/// trace continued by the requests (a new trace is started for each request otherwise)
+	#[cfg(feature = "tracing")]
+	trace_context: Option<TraceContext>,
+}
+
+/// Index of the node a request was built for, carried in the extensions of the request
+#[derive(Debug, Clone, Copy)]
+struct Node(usize);
+
+/// Wrapper around the data part of a vault response
+#[derive(Debug, Deserialize)]
+struct DataResponse<T> {
+	data: T,
+}
+
+/// Body of a login request
+#[derive(Serialize)]
+struct Login<'a> {
+	role: &'a str,
+	jwt: &'a str,
 }
 
 impl VaultClient {
-	/// Create a new vault client given an url, a jwt token and a ca certificate path
+	/// Create a new vault client given an url, a jwt token and a ca certificate path.
+	/// url can be a comma separated list of addresses of the nodes of a vault cluster. An address
+	/// like `unix:///path/to/agent.sock` reaches the api of a vault agent over a unix socket
+	/// (an error on other platforms)
 	pub fn new(url: &str, login_path: &str, jwt: &str, cacert_path: Option<&str>) -> Result<Self> {
-		let mut builder = HttpClient::builder().default_header("Content-Type", "application/json");
+		let urls: Vec<String> = url
+			.split(',')
+			.map(|u| u.trim().trim_end_matches('/'))
+			.filter(|u| !u.is_empty())
+			.map(str::to_owned)
+			.collect();
+		if urls.is_empty() {
+			return Err(Error::NoAddress);
+		}
+		#[cfg(not(unix))]
+		if let Some(url) = urls.iter().find(|u| u.starts_with(UNIX_SCHEME)) {
+			return Err(Error::UnsupportedSocket(url.to_owned()));
+		}
+		let mut builder = HttpClient::builder()
+			.default_header("Content-Type", "application/json")
+			.redirect_policy(RedirectPolicy::Limit(MAX_REDIRECTS));
 		if let Some(cacert) = cacert_path {
 			builder = builder.ssl_ca_certificate(CaCertificate::file(cacert));
 		}
 		let client = builder.build()?;
 		Ok(VaultClient {
 			url: url.to_owned(),
+			urls,
+			active: AtomicUsize::new(0),
 			login_path: login_path.to_owned(),
-			jwt: jwt.to_owned(),
+			jwt: JwtSource::Static(Jwt::new(jwt)),
 			client,
 			auth: HashMap::new(),
+			revoke_on_drop: false,
+			cache: None,
+			renew_policy: RenewPolicy::default(),
+			metrics: Metrics::default(),
+			middlewares: Middlewares::default(),
+			agent_mode: false,
+			#[cfg(all(unix, feature = "token-store"))]
+			token_store: None,
+			#[cfg(all(unix, feature = "token-store"))]
+			unverified: std::collections::HashSet::new(),
+			#[cfg(feature = "tracing")]
+			trace_context: None,
 		})
 	}
 
@@ -47,91 +152,480 @@ impl VaultClient {
 			.is_some()
 	}
 
-	/// Log in to the vault client and return Auth.
+	/// Return the token associated to a role
+	fn token(&self, role: &str) -> Result<&str> {
+		self.auth
+			.get(role)
+			.map(|auth| auth.client_token.expose())
+			.ok_or(Error::NotLogged)
+	}
+
+	/// Return the token to send with the requests of role, or None in agent mode if role is not
+	/// logged in
+	fn request_token(&self, role: &str) -> Result<Option<&str>> {
+		match self.token(role) {
+			Err(Error::NotLogged) if self.agent_mode => Ok(None),
+			res => res.map(Some),
+		}
+	}
+
+	/// Return the address of the node requests are sent to
+	pub fn url(&self) -> &str {
+		&self.urls[self.active.load(Ordering::Relaxed)]
+	}
+
+	/// Build a request to the vault api of the current node with an optional token
+	fn request(
+		&self,
+		method: &str,
+		path: &str,
+		token: Option<&str>,
+		body: String,
+	) -> Result<Request<String>> {
+		let node = self.active.load(Ordering::Relaxed);
+		let mut request = request_to(&self.urls[node], method, path, token, body)?;
+		request.extensions_mut().insert(Node(node));
+		Ok(request)
+	}
+
+	/// Return the index of the node a request was built for (the current node by default)
+	fn node_of(&self, request: &Request<String>) -> usize {
+		request
+			.extensions()
+			.get::<Node>()
+			.map_or_else(|| self.active.load(Ordering::Relaxed), |node| node.0)
+	}
+
+	/// Send a request and parse the json response or the vault errors
+	fn send<T: DeserializeOwned>(&self, mut request: Request<String>) -> Result<T> {
+		let path = api_path(&self.urls[self.node_of(&request)], &request);
+		self.middlewares.before(&mut request)?;
+		let (method, uri, start) = (
+			request.method().clone(),
+			request.uri().clone(),
+			Instant::now(),
+		);
+		#[cfg(feature = "tracing")]
+		let span = trace::request_span(&mut request, &path, self.trace_parent().as_ref());
+		#[cfg(feature = "tracing")]
+		let _enter = span.enter();
+		let res = self.send_failover(request);
+		let (status, latency) = (
+			res.as_ref().ok().map(|res| res.status().as_u16()),
+			start.elapsed(),
+		);
+		self.metrics.request(&path, status, latency);
+		#[cfg(feature = "tracing")]
+		trace::record_response(&span, status, latency);
+		self.after(&method, &uri, res.as_ref().ok(), latency);
+		parse_response(res?)
+	}
+
+	/// Send a request asynchronously and parse the json response or the vault errors
+	async fn send_async<T: DeserializeOwned + Unpin>(
+		&self,
+		mut request: Request<String>,
+	) -> Result<T> {
+		let path = api_path(&self.urls[self.node_of(&request)], &request);
+		self.middlewares.before(&mut request)?;
+		let (method, uri, start) = (
+			request.method().clone(),
+			request.uri().clone(),
+			Instant::now(),
+		);
+		#[cfg(feature = "tracing")]
+		let span = trace::request_span(&mut request, &path, self.trace_parent().as_ref());
+		#[cfg(feature = "tracing")]
+		let res = {
+			use tracing::Instrument;
+			self.send_failover_async(request)
+				.instrument(span.clone())
+				.await
+		};
+		#[cfg(not(feature = "tracing"))]
+		let res = self.send_failover_async(request).await;
+		let (status, latency) = (
+			res.as_ref().ok().map(|res| res.status().as_u16()),
+			start.elapsed(),
+		);
+		self.metrics.request(&path, status, latency);
+		#[cfg(feature = "tracing")]
+		trace::record_response(&span, status, latency);
+		self.after(&method, &uri, res.as_ref().ok(), latency);
+		parse_response_async(res?).await
+	}
+
+	/// Run the after hooks of the middlewares with the response of a request if any
+	fn after<B>(&self, method: &Method, uri: &Uri, res: Option<&Response<B>>, latency: Duration) {
+		self.middlewares.after(&Exchange {
+			method,
+			uri,
+			status: res.map(|res| res.status()),
+			headers: res.map(|res| res.headers()),
+			latency,
+		});
+	}
+
+	/// Send a request to the current node and fail over to another node of the cluster on
+	/// connection errors
+	fn send_failover(&self, mut request: Request<String>) -> Result<Response<Body>> {
+		let mut node = self.node_of(&request);
+		let mut tried = vec![node];
+		loop {
+			let retry = copy_request(&request)?;
+			match self.client.send(request) {
+				Err(e) if (e.is_network() || e.is_timeout()) && tried.len() < self.urls.len() => {
+					let from = &self.urls[node];
+					log::warn!("vault node {} unreachable: {}", from, e);
+					let path = match relative_path(from, &retry) {
+						Some(path) => path,
+						None => return Err(Error::ClientError { source: e }),
+					};
+					let healths = self
+						.urls
+						.iter()
+						.enumerate()
+						.map(|(i, url)| (i, self.probe(&tried, i, url)));
+					let next = select_node(healths).ok_or(Error::ClientError { source: e })?;
+					tried.push(next);
+					request = self.switch_node(retry, &path, next)?;
+					node = next;
+				}
+				res => return res.map_err(|e| Error::ClientError { source: e }),
+			}
+		}
+	}
+
+	/// Send a request asynchronously to the current node and fail over to another node of the
+	/// cluster on connection errors
+	async fn send_failover_async(
+		&self,
+		mut request: Request<String>,
+	) -> Result<Response<AsyncBody>> {
+		let mut node = self.node_of(&request);
+		let mut tried = vec![node];
+		loop {
+			let retry = copy_request(&request)?;
+			match self.client.send_async(request).await {
+				Err(e) if (e.is_network() || e.is_timeout()) && tried.len() < self.urls.len() => {
+					let from = &self.urls[node];
+					log::warn!("vault node {} unreachable: {}", from, e);
+					let path = match relative_path(from, &retry) {
+						Some(path) => path,
+						None => return Err(Error::ClientError { source: e }),
+					};
+					let mut healths = Vec::with_capacity(self.urls.len());
+					for (i, url) in self.urls.iter().enumerate() {
+						healths.push((i, self.probe_async(&tried, i, url).await));
+					}
+					let next =
+						select_node(healths.into_iter()).ok_or(Error::ClientError { source: e })?;
+					tried.push(next);
+					request = self.switch_node(retry, &path, next)?;
+					node = next;
+				}
+				res => return res.map_err(|e| Error::ClientError { source: e }),
+			}
+		}
+	}
+
+	/// Return the health of a node not already tried
+	fn probe(&self, tried: &[usize], i: usize, url: &str) -> Option<Health> {
+		if tried.contains(&i) {
+			return None;
+		}
+		let mut request = request_to(url, "GET", HEALTH_PATH, None, String::new()).ok()?;
+		self.middlewares.before(&mut request).ok()?;
+		let (method, uri, start) = (
+			request.method().clone(),
+			request.uri().clone(),
+			Instant::now(),
+		);
+		let res = self.client.send(request);
+		self.after(&method, &uri, res.as_ref().ok(), start.elapsed());
+		parse_response(res.ok()?).ok()
+	}
+
+	/// Return asynchronously the health of a node not already tried
+	async fn probe_async(&self, tried: &[usize], i: usize, url: &str) -> Option<Health> {
+		if tried.contains(&i) {
+			return None;
+		}
+		let mut request = request_to(url, "GET", HEALTH_PATH, None, String::new()).ok()?;
+		self.middlewares.before(&mut request).ok()?;
+		let (method, uri, start) = (
+			request.method().clone(),
+			request.uri().clone(),
+			Instant::now(),
+		);
+		let res = self.client.send_async(request).await;
+		self.after(&method, &uri, res.as_ref().ok(), start.elapsed());
+		parse_response_async(res.ok()?).await.ok()
+	}
+
+	/// Make node the current node and retarget the request to the api path of node
+	fn switch_node(
+		&self,
+		request: Request<String>,
+		path: &str,
+		node: usize,
+	) -> Result<Request<String>> {
+		let to = &self.urls[node];
+		log::info!("failing over to vault node {}", to);
+		self.active.store(node, Ordering::Relaxed);
+		let mut builder = builder_to(to, path)
+			.method(request.method())
+			.extension(Node(node));
+		if let Some(headers) = builder.headers_mut() {
+			*headers = request.headers().clone();
+		}
+		builder
+			.body(request.into_body())
+			.map_err(|e| Error::HttpError { source: e })
+	}
+
+	/// Probe the health of all nodes and make the active node (or a ready standby node which will
+	/// redirect to the active one) the current node
+	pub fn select_active(&self) -> Result<&str> {
+		let healths = self
+			.urls
+			.iter()
+			.enumerate()
+			.map(|(i, url)| (i, self.probe(&[], i, url)));
+		let node = select_node(healths).ok_or(Error::NoActiveNode)?;
+		self.active.store(node, Ordering::Relaxed);
+		Ok(self.url())
+	}
+
+	/// Probe asynchronously the health of all nodes and make the active node (or a ready standby
+	/// node which will redirect to the active one) the current node
+	pub async fn select_active_async(&self) -> Result<&str> {
+		let mut healths = Vec::with_capacity(self.urls.len());
+		for (i, url) in self.urls.iter().enumerate() {
+			healths.push((i, self.probe_async(&[], i, url).await));
+		}
+		let node = select_node(healths.into_iter()).ok_or(Error::NoActiveNode)?;
+		self.active.store(node, Ordering::Relaxed);
+		Ok(self.url())
+	}
+
+	/// Build the login request
+	fn login_request(&self, role: &str) -> Result<Request<String>> {
+		let jwt = self.jwt.read()?;
+		// fail early with an explicit error instead of an opaque vault error
+		match Claims::decode(jwt.expose()) {
+			Ok(claims) => {
+				log::debug!(
+					"login with role {} (iss: {:?}, sub: {:?}, aud: {:?})",
+					role,
+					claims.iss,
+					claims.sub,
+					claims.aud
+				);
+				claims.check_now()?;
+			}
+			Err(e) => log::warn!("unable to decode jwt from {}: {}", self.jwt, e),
+		}
+		// serialized without an intermediate copy of the jwt
+		let body = serde_json::to_string(&Login {
+			role,
+			jwt: jwt.expose(),
+		})
+		.map_err(|e| Error::ParseError { source: e })?;
+		self.request("POST", &self.login_path, None, body)
+	}
+
+	/// Check if the token of role is still valid and can be renewed instead of logging in again
+	fn can_renew(&self, role: &str) -> bool {
+		self.auth
+			.get(role)
+			.filter(|v| v.is_valid() && v.is_renewable())
+			.is_some()
+	}
+
+	/// Log in to the vault client and return Auth. A renewable token about to expire is renewed,
+	/// otherwise a new token is obtained with a fresh login.
+	#[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(role = %role)))]
 	pub fn login(&mut self, role: &str) -> Result<&Auth> {
+		self.verify_stored(role);
 		if !self.is_logged(role) {
-			let url = format!("{}{}", &self.url, &self.login_path);
-			let body = json!({"role": role, "jwt": &self.jwt}).to_string();
-			let mut res = self
-				.client
-				.post(url, body)
-				.map_err(|e| Error::ClientError { source: e })?;
-			let status = res.status();
-			if status == StatusCode::OK {
-				// parse vault response and cache important information
-				let auth_value: Value = res.json().map_err(|e| Error::ParseError { source: e })?;
-				let lease_duration = auth_value["auth"]["lease_duration"]
-					.as_u64()
-					.unwrap_or(0u64);
-				let renewable = auth_value["auth"]["renewable"].as_bool().unwrap_or(false);
-				let auth = Auth::new(
-					auth_value["auth"]["client_token"].as_str().unwrap_or(""),
-					if renewable {
-						Some(Duration::from_secs(lease_duration))
-					} else {
-						None
-					},
-				);
-				// insert and forget old value if any
-				let _ = self.auth.insert(role.to_owned(), auth);
-			} else {
-				// parse vault error
-				let errors: VaultErrors =
-					res.json().map_err(|e| Error::ParseError { source: e })?;
-				return Err(Error::VaultError(status, errors.errors.join("\n")));
+			if self.can_renew(role) {
+				match self.renew_self(role) {
+					Ok(_) => return Ok(self.auth.get(role).unwrap()),
+					Err(e) => log::warn!("unable to renew token of role {}: {}", role, e),
+				}
 			}
+			let res: Result<AuthResponse> = self.send(self.login_request(role)?);
+			self.metrics.login(role, res.is_ok());
+			let res = res?;
+			// insert and forget old value if any
+			let auth = res.auth.into_auth(&self.renew_policy);
+			let _ = self.auth.insert(role.to_owned(), auth);
+			self.persist(role);
 		}
 
 		Ok(self.auth.get(role).unwrap())
 	}
 
-	/// Log in asynchronously to the vault client and return Auth.
+	/// Log in asynchronously to the vault client and return Auth. A renewable token about to
+	/// expire is renewed, otherwise a new token is obtained with a fresh login.
+	#[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(role = %role)))]
 	pub async fn login_async(&mut self, role: &str) -> Result<&Auth> {
+		self.verify_stored_async(role).await;
 		// login if we are not already logged in or if it's time to renew token
-
 		if !self.is_logged(role) {
-			let url = format!("{}{}", &self.url, &self.login_path);
-			let body = json!({"role": role, "jwt": &self.jwt}).to_string();
-			let mut res = self
-				.client
-				.post_async(url, body)
-				.await
-				.map_err(|e| Error::ClientError { source: e })?;
-			let status = res.status();
-			if status == StatusCode::OK {
-				// parse vault response and cache important information
-				let auth_value: Value = res
-					.json()
-					.await
-					.map_err(|e| Error::ParseError { source: e })?;
-				let lease_duration = auth_value["auth"]["lease_duration"]
-					.as_u64()
-					.unwrap_or(0u64);
-				let renewable = auth_value["auth"]["renewable"].as_bool().unwrap_or(false);
-				let auth = Auth::new(
-					auth_value["auth"]["client_token"].as_str().unwrap_or(""),
-					if renewable {
-						Some(Duration::from_secs(lease_duration))
-					} else {
-						None
-					},
-				);
-				// insert and forget old value if any
-				let _ = self.auth.insert(role.to_owned(), auth);
-			} else {
-				// parse vault error
-				let errors: VaultErrors = res
-					.json()
-					.await
-					.map_err(|e| Error::ParseError { source: e })?;
-				return Err(Error::VaultError(status, errors.errors.join("\n")));
+			if self.can_renew(role) {
+				match self.renew_self_async(role).await {
+					Ok(_) => return Ok(self.auth.get(role).unwrap()),
+					Err(e) => log::warn!("unable to renew token of role {}: {}", role, e),
+				}
 			}
+			let res: Result<AuthResponse> = self.send_async(self.login_request(role)?).await;
+			self.metrics.login(role, res.is_ok());
+			let res = res?;
+			// insert and forget old value if any
+			let auth = res.auth.into_auth(&self.renew_policy);
+			let _ = self.auth.insert(role.to_owned(), auth);
+			self.persist(role);
 		}
 
 		Ok(self.auth.get(role).unwrap())
 	}
 
+	/// Build the request for renewing a token
+	fn renew_request(&self, role: &str) -> Result<Request<String>> {
+		self.request(
+			"POST",
+			"auth/token/renew-self",
+			Some(self.token(role)?),
+			String::new(),
+		)
+	}
+
+	/// Renew the token associated to role and return the updated Auth
+	#[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(role = %role)))]
+	pub fn renew_self(&mut self, role: &str) -> Result<&Auth> {
+		let res: Result<AuthResponse> = self.send(self.renew_request(role)?);
+		self.metrics.renewal(metrics::TOKEN, res.is_ok());
+		let res = res?;
+		let auth = res.auth.into_auth(&self.renew_policy);
+		let _ = self.auth.insert(role.to_owned(), auth);
+		self.persist(role);
+		Ok(self.auth.get(role).unwrap())
+	}
+
+	/// Renew asynchronously the token associated to role and return the updated Auth
+	#[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(role = %role)))]
+	pub async fn renew_self_async(&mut self, role: &str) -> Result<&Auth> {
+		let res: Result<AuthResponse> = self.send_async(self.renew_request(role)?).await;
+		self.metrics.renewal(metrics::TOKEN, res.is_ok());
+		let res = res?;
+		let auth = res.auth.into_auth(&self.renew_policy);
+		let _ = self.auth.insert(role.to_owned(), auth);
+		self.persist(role);
+		Ok(self.auth.get(role).unwrap())
+	}
+
+	/// Build the request for fetching a secret
+	fn secret_request(
+		&self,
+		role: &str,
+		method: &str,
+		path: &str,
+		kwargs: Option<&Vec<(&str, &str)>>,
+	) -> Result<Request<String>> {
+		let token = self.request_token(role)?;
+		// transform the kwargs into a json object
+		let body = kwargs
+			.map(|kwargs| {
+				kwargs.iter().fold(Map::new(), |mut m, (k, v)| {
+					m.insert((*k).to_owned(), Value::String((*v).to_owned()));
+					m
+				})
+			})
+			.map(Value::Object)
+			.unwrap_or(Value::Null);
+		self.request(method, path, token, body.to_string())
+	}
+
+	/// Enable the cache of secrets. Requests with non idempotent methods (like `POST pki/issue`)
+	/// are only cached if cache_non_idempotent is true
+	pub fn enable_cache(&mut self, cache_non_idempotent: bool) {
+		self.cache = Some(Mutex::new(SecretCache::new(cache_non_idempotent)));
+	}
+
+	/// Disable and drop the cache of secrets
+	pub fn disable_cache(&mut self) {
+		self.cache = None;
+	}
+
+	/// Return the statistics of the cache of secrets if enabled
+	pub fn cache_stats(&self) -> Option<CacheStats> {
+		self.cache
+			.as_ref()
+			.map(|cache| cache.lock().unwrap().stats())
+	}
+
+	/// Return the metrics of the client with the time until each token and leased secret expires
+	pub fn metrics(&self) -> MetricsSnapshot {
+		let mut snapshot = self.metrics.snapshot();
+		snapshot.cache = self.cache_stats();
+		snapshot.token_expiry = self
+			.auth
+			.iter()
+			.filter_map(|(role, auth)| {
+				auth.lease
+					.as_ref()
+					.map(|lease| (role.to_owned(), lease.remaining()))
+			})
+			.collect();
+		snapshot
+	}
+
+	/// Evict all expired secrets from the cache
+	pub fn purge_cache(&self) {
+		if let Some(ref cache) = self.cache {
+			cache.lock().unwrap().purge();
+		}
+	}
+
+	/// Return the cache key of a request if the cache is enabled and the request is cacheable
+	fn cache_key(
+		&self,
+		role: &str,
+		method: &str,
+		path: &str,
+		kwargs: Option<&Vec<(&str, &str)>>,
+	) -> Option<CacheKey> {
+		self.cache
+			.as_ref()
+			.filter(|cache| cache.lock().unwrap().is_cacheable(method))
+			.map(|_| CacheKey::new(role, method, path, kwargs))
+	}
+
+	/// Return a valid cached secret
+	fn cached_secret(&self, key: &CacheKey) -> Option<Secret> {
+		self.cache
+			.as_ref()
+			.and_then(|cache| cache.lock().unwrap().get(key))
+	}
+
+	/// Cache a secret
+	fn cache_secret(&self, key: CacheKey, secret: &Secret) {
+		if let Some(ref cache) = self.cache {
+			cache.lock().unwrap().insert(key, secret);
+		}
+	}
+
+	/// Remove the cached secrets of a role
+	fn uncache_role(&self, role: &str) {
+		if let Some(ref cache) = self.cache {
+			cache.lock().unwrap().remove_role(role);
+		}
+	}
+
 	/// Get a secret from vault server and reschedule a renew with role if necessary
+	#[cfg_attr(feature = "tracing", tracing::instrument(skip_all, err, fields(role = %role, method = %method, path = %path)))]
 	pub fn get_secret(
 		&self,
 		role: &str,
@@ -139,56 +633,23 @@ impl VaultClient {
 		path: &str,
 		kwargs: Option<&Vec<(&str, &str)>>,
 	) -> Result<Secret> {
-		if let Some(auth) = self.auth.get(role) {
-			let uri = format!("{}/{}", &self.url, path);
-			// transform the kwargs into a json object
-			let body = kwargs
-				.map(|kwargs| {
-					kwargs.iter().fold(Map::new(), |mut m, (k, v)| {
-						m.insert((*k).to_owned(), Value::String((*v).to_owned()));
-						m
-					})
-				})
-				.map(|o| Value::Object(o))
-				.unwrap_or(Value::Null);
-			// build the request
-			let request = Request::builder()
-				.uri(uri)
-				.method(method)
-				.header("X-Vault-Token", auth.client_token.as_str())
-				.body(body.to_string())
-				.map_err(|e| Error::HttpError { source: e })?;
-			// async send the request
-			let mut res = self
-				.client
-				.send(request)
-				.map_err(|e| Error::ClientError { source: e })?;
-			// handle the response
-			let status = res.status();
-			return if status == StatusCode::OK {
-				// parse vault response
-				let mut secret_value: Value =
-					res.json().map_err(|e| Error::ParseError { source: e })?;
-
-				let duration = secret_value
-					.get("lease_duration")
-					.map(|o| o.as_u64().unwrap_or(0u64))
-					.filter(|o| *o != 0u64)
-					.map(|o| Duration::from_secs(o * 2 / 3));
-				// return the parsed secret (only the data part)
-				Ok(Secret::new(secret_value["data"].take(), duration))
-			} else {
-				// parse vault error
-				let errors: VaultErrors =
-					res.json().map_err(|e| Error::ParseError { source: e })?;
-				Err(Error::VaultError(status, errors.errors.join("\n")))
-			};
-		} else {
-			Err(Error::NotLogged)
+		// a logged out role is not served from the cache
+		self.request_token(role)?;
+		let key = self.cache_key(role, method, path, kwargs);
+		if let Some(secret) = key.as_ref().and_then(|key| self.cached_secret(key)) {
+			return Ok(secret);
+		}
+		let secret_value: Value = self.send(self.secret_request(role, method, path, kwargs)?)?;
+		let secret = to_secret(secret_value, &self.renew_policy);
+		self.metrics.secret(&secret);
+		if let Some(key) = key {
+			self.cache_secret(key, &secret);
 		}
+		Ok(secret)
 	}
 
 	/// Get a secret asynchronously from vault server and reschedule a renew with role if necessary
+	#[cfg_attr(feature = "tracing", tracing::instrument(skip_all, err, fields(role = %role, method = %method, path = %path)))]
 	pub async fn get_secret_async(
 		&self,
 		role: &str,
@@ -196,112 +657,1399 @@ impl VaultClient {
 		path: &str,
 		kwargs: Option<&Vec<(&str, &str)>>,
 	) -> Result<Secret> {
-		if let Some(auth) = self.auth.get(role) {
-			let uri = format!("{}/{}", &self.url, path);
-			// transform the kwargs into a json object
-			let body = kwargs
-				.map(|kwargs| {
-					kwargs.iter().fold(Map::new(), |mut m, (k, v)| {
-						m.insert((*k).to_owned(), Value::String((*v).to_owned()));
-						m
-					})
-				})
-				.map(|o| Value::Object(o))
-				.unwrap_or(Value::Null);
-			// build the request
-			let request = Request::builder()
-				.uri(uri)
-				.method(method)
-				.header("X-Vault-Token", auth.client_token.as_str())
-				.body(body.to_string())
-				.map_err(|e| Error::HttpError { source: e })?;
-			// async send the request
-			let mut res = self
-				.client
-				.send_async(request)
-				.await
-				.map_err(|e| Error::ClientError { source: e })?;
-			// handle the response
-			let status = res.status();
-			return if status == StatusCode::OK {
-				// parse vault response
-				let mut secret_value: Value = res
-					.json()
-					.await
-					.map_err(|e| Error::ParseError { source: e })?;
-
-				let duration = secret_value
-					.get("lease_duration")
-					.map(|o| o.as_u64().unwrap_or(0u64))
-					.filter(|o| *o != 0u64)
-					.map(|o| Duration::from_secs(o * 2 / 3));
-				// return the parsed secret (only the data part)
-				Ok(Secret::new(secret_value["data"].take(), duration))
-			} else {
-				// parse vault error
-				let errors: VaultErrors = res
-					.json()
-					.await
-					.map_err(|e| Error::ParseError { source: e })?;
-				Err(Error::VaultError(status, errors.errors.join("\n")))
-			};
-		} else {
-			Err(Error::NotLogged)
+		// a logged out role is not served from the cache
+		self.request_token(role)?;
+		let key = self.cache_key(role, method, path, kwargs);
+		if let Some(secret) = key.as_ref().and_then(|key| self.cached_secret(key)) {
+			return Ok(secret);
+		}
+		let secret_value: Value = self
+			.send_async(self.secret_request(role, method, path, kwargs)?)
+			.await?;
+		let secret = to_secret(secret_value, &self.renew_policy);
+		self.metrics.secret(&secret);
+		if let Some(key) = key {
+			self.cache_secret(key, &secret);
+		}
+		Ok(secret)
+	}
+
+	/// Build the request for renewing the lease of a secret
+	fn renew_secret_request(&self, role: &str, secret: &Secret) -> Result<Request<String>> {
+		let lease_id = secret.lease_id().ok_or(Error::NoLease)?;
+		let body = json!({ "lease_id": lease_id }).to_string();
+		self.request("PUT", "sys/leases/renew", self.request_token(role)?, body)
+	}
+
+	/// Renew the lease of a secret and return the secret with its new lease
+	#[cfg_attr(feature = "tracing", tracing::instrument(skip_all, err, fields(role = %role)))]
+	pub fn renew_secret(&self, role: &str, secret: &Secret) -> Result<Secret> {
+		let res: Result<Value> = self.send(self.renew_secret_request(role, secret)?);
+		self.metrics.renewal(metrics::SECRET, res.is_ok());
+		let renewed = renewed_secret(secret, res?, &self.renew_policy);
+		self.metrics.secret(&renewed);
+		Ok(renewed)
+	}
+
+	/// Renew asynchronously the lease of a secret and return the secret with its new lease
+	#[cfg_attr(feature = "tracing", tracing::instrument(skip_all, err, fields(role = %role)))]
+	pub async fn renew_secret_async(&self, role: &str, secret: &Secret) -> Result<Secret> {
+		let res: Result<Value> = self
+			.send_async(self.renew_secret_request(role, secret)?)
+			.await;
+		self.metrics.renewal(metrics::SECRET, res.is_ok());
+		let renewed = renewed_secret(secret, res?, &self.renew_policy);
+		self.metrics.secret(&renewed);
+		Ok(renewed)
+	}
+
+	/// Log in with the role of a secret path, fetch the secret and return the value pointed by
+	/// its anchor
+	pub fn resolve(&mut self, path: &SecretPath<'_, Backend>) -> Result<SecretValue> {
+		match path.backend {
+			Backend::Vault => {
+				let role = path.role()?;
+				if !self.agent_mode {
+					self.login(role)?;
+				}
+				let secret =
+					self.get_secret(role, path.method(), path.path, path.kwargs.as_ref())?;
+				path.select_secret(secret.value)
+			}
+		}
+	}
+
+	/// Log in asynchronously with the role of a secret path, fetch the secret and return the
+	/// value pointed by its anchor
+	pub async fn resolve_async(&mut self, path: &SecretPath<'_, Backend>) -> Result<SecretValue> {
+		match path.backend {
+			Backend::Vault => {
+				let role = path.role()?;
+				if !self.agent_mode {
+					self.login_async(role).await?;
+				}
+				let secret = self
+					.get_secret_async(role, path.method(), path.path, path.kwargs.as_ref())
+					.await?;
+				path.select_secret(secret.value)
+			}
+		}
+	}
+
+	/// Resolve many secret paths and return the result of each path in the same order. Each
+	/// role logs in once and identical requests are sent once, with at most `concurrency`
+	/// requests in flight over the shared connection pool
+	pub fn resolve_all(
+		&mut self,
+		paths: &[SecretPath<'_, Backend>],
+		concurrency: usize,
+	) -> Vec<Result<SecretValue>> {
+		let (fetches, slots) = plan(paths);
+		let mut failed_logins = HashMap::new();
+		if !self.agent_mode {
+			for role in roles(&fetches) {
+				if let Err(e) = self.login(role) {
+					failed_logins.insert(role, Arc::new(e));
+				}
+			}
+		}
+		let next = AtomicUsize::new(0);
+		let fetched = Mutex::new(Vec::with_capacity(fetches.len()));
+		let client = &*self;
+		#[cfg(feature = "tracing")]
+		let parent = TraceContext::current();
+		thread::scope(|s| {
+			for _ in 0..concurrency.clamp(1, fetches.len().max(1)) {
+				s.spawn(|| {
+					// the workers send their requests in the trace entered by the caller
+					#[cfg(feature = "tracing")]
+					let _scope = parent.map(TraceContext::enter);
+					loop {
+						let i = next.fetch_add(1, Ordering::Relaxed);
+						let path = match fetches.get(i) {
+							Some(path) => path,
+							None => break,
+						};
+						let secret = client.fetch(path, &failed_logins);
+						fetched.lock().unwrap().push((i, secret));
+					}
+				});
+			}
+		});
+		assemble(paths, slots, fetched.into_inner().unwrap())
+	}
+
+	/// Resolve asynchronously many secret paths and return the result of each path in the same
+	/// order. Each role logs in once and identical requests are sent once, with at most
+	/// `concurrency` requests in flight over the shared connection pool
+	pub async fn resolve_all_async(
+		&mut self,
+		paths: &[SecretPath<'_, Backend>],
+		concurrency: usize,
+	) -> Vec<Result<SecretValue>> {
+		let (fetches, slots) = plan(paths);
+		let mut failed_logins = HashMap::new();
+		if !self.agent_mode {
+			for role in roles(&fetches) {
+				if let Err(e) = self.login_async(role).await {
+					failed_logins.insert(role, Arc::new(e));
+				}
+			}
 		}
+		let (client, failed_logins) = (&*self, &failed_logins);
+		let fetched = stream::iter(fetches.iter().enumerate())
+			.map(|(i, path)| async move { (i, client.fetch_async(path, failed_logins).await) })
+			.buffer_unordered(concurrency.max(1))
+			.collect()
+			.await;
+		assemble(paths, slots, fetched)
+	}
+
+	/// Get the secret of a path of `resolve_all` unless the login of its role failed
+	fn fetch(
+		&self,
+		path: &SecretPath<'_, Backend>,
+		failed_logins: &HashMap<&str, Arc<Error>>,
+	) -> Fetched {
+		let role = path.role()?;
+		if let Some(e) = failed_logins.get(role) {
+			return Err(e.clone());
+		}
+		Ok(self.get_secret(role, path.method(), path.path, path.kwargs.as_ref())?)
+	}
+
+	/// Get asynchronously the secret of a path of `resolve_all_async` unless the login of its
+	/// role failed
+	async fn fetch_async(
+		&self,
+		path: &SecretPath<'_, Backend>,
+		failed_logins: &HashMap<&str, Arc<Error>>,
+	) -> Fetched {
+		let role = path.role()?;
+		if let Some(e) = failed_logins.get(role) {
+			return Err(e.clone());
+		}
+		Ok(self
+			.get_secret_async(role, path.method(), path.path, path.kwargs.as_ref())
+			.await?)
 	}
 
 	/// Return a hashmap of mountpoints and backend type concatenated with `options.version` if present
 	/// the given role should have read access to vault api /sys/mounts
+	#[cfg_attr(feature = "tracing", tracing::instrument(skip_all, err, fields(role = %role)))]
 	pub fn get_mounts(&self, role: &str) -> Result<HashMap<String, String>> {
-		if let Some(auth) = self.auth.get(role) {
-			let uri = format!("{}/sys/mounts", &self.url);
-			let request = Request::builder()
-				.uri(uri)
-				.method("GET")
-				.header("X-Vault-Token", auth.client_token.as_str())
-				.body(())
-				.map_err(|e| Error::HttpError { source: e })?;
-			let mut res = self
-				.client
-				.send(request)
-				.map_err(|e| Error::ClientError { source: e })?;
-			let status = res.status();
-			return if status == StatusCode::OK {
-				// parse vault response
-				let mounts_value: Value =
-					res.json().map_err(|e| Error::ParseError { source: e })?;
-
-				if let Some(Value::Object(map)) = mounts_value.get("data") {
-					let mut mounts = HashMap::new();
-					for (mount_point, mount) in map.iter() {
-						let mount_type = mount
-							.get("type")
-							.and_then(|v| v.as_str())
-							.ok_or_else(|| Error::UndefinedMountType(mount_point.to_owned()))?;
-						let mount_version = mount
-							.get("options")
-							.and_then(|v| v.get("version"))
-							.and_then(|v| v.as_str());
-						mounts.insert(
-							mount_point.to_owned(),
-							mount_version
-								.and_then(|s| Some(mount_type.to_owned() + s))
-								.or(Some(mount_type.to_owned()))
-								.unwrap(),
-						);
-					}
-					Ok(mounts)
-				} else {
-					Err(Error::MountsNotFound)
+		let request = self.request(
+			"GET",
+			"sys/mounts",
+			self.request_token(role)?,
+			String::new(),
+		)?;
+		let mounts_value: Value = self.send(request)?;
+		to_mounts(mounts_value)
+	}
+
+	/// Return the properties of the token associated to role
+	pub fn lookup_self(&self, role: &str) -> Result<TokenInfo> {
+		let request = self.request(
+			"GET",
+			"auth/token/lookup-self",
+			self.request_token(role)?,
+			String::new(),
+		)?;
+		let res: DataResponse<TokenInfo> = self.send(request)?;
+		Ok(res.data)
+	}
+
+	/// Return asynchronously the properties of the token associated to role
+	pub async fn lookup_self_async(&self, role: &str) -> Result<TokenInfo> {
+		let request = self.request(
+			"GET",
+			"auth/token/lookup-self",
+			self.request_token(role)?,
+			String::new(),
+		)?;
+		let res: DataResponse<TokenInfo> = self.send_async(request).await?;
+		Ok(res.data)
+	}
+
+	/// Build the request for getting the capabilities of a token on some paths
+	fn capabilities_request(&self, role: &str, paths: &[&str]) -> Result<Request<String>> {
+		let body = json!({ "paths": paths }).to_string();
+		self.request(
+			"POST",
+			"sys/capabilities-self",
+			self.request_token(role)?,
+			body,
+		)
+	}
+
+	/// Return the capabilities of the token associated to role on each of the given paths
+	pub fn capabilities(&self, role: &str, paths: &[&str]) -> Result<HashMap<String, Vec<String>>> {
+		let value: Value = self.send(self.capabilities_request(role, paths)?)?;
+		Ok(to_capabilities(&value, paths))
+	}
+
+	/// Return asynchronously the capabilities of the token associated to role on each of the
+	/// given paths
+	pub async fn capabilities_async(
+		&self,
+		role: &str,
+		paths: &[&str],
+	) -> Result<HashMap<String, Vec<String>>> {
+		let value: Value = self
+			.send_async(self.capabilities_request(role, paths)?)
+			.await?;
+		Ok(to_capabilities(&value, paths))
+	}
+
+	/// Check secret paths against the capabilities of the token of their role and return the
+	/// ones that would be denied. One capabilities request is sent per role
+	pub fn denied_paths<'p, 'a>(
+		&self,
+		paths: &'p [SecretPath<'a, Backend>],
+	) -> Result<Vec<&'p SecretPath<'a, Backend>>> {
+		let mut caps = HashMap::new();
+		for (role, role_paths) in paths_by_role(paths)? {
+			caps.insert(role, self.capabilities(role, &role_paths)?);
+		}
+		Ok(denied(paths, &caps))
+	}
+
+	/// Check asynchronously secret paths against the capabilities of the token of their role
+	/// and return the ones that would be denied. One capabilities request is sent per role
+	pub async fn denied_paths_async<'p, 'a>(
+		&self,
+		paths: &'p [SecretPath<'a, Backend>],
+	) -> Result<Vec<&'p SecretPath<'a, Backend>>> {
+		let mut caps = HashMap::new();
+		for (role, role_paths) in paths_by_role(paths)? {
+			caps.insert(role, self.capabilities_async(role, &role_paths).await?);
+		}
+		Ok(denied(paths, &caps))
+	}
+
+	/// Return the health of the vault server (no login required)
+	pub fn health(&self) -> Result<Health> {
+		self.send(self.request("GET", HEALTH_PATH, None, String::new())?)
+	}
+
+	/// Return asynchronously the health of the vault server (no login required)
+	pub async fn health_async(&self) -> Result<Health> {
+		self.send_async(self.request("GET", HEALTH_PATH, None, String::new())?)
+			.await
+	}
+
+	/// Return the seal status of the vault server (no login required)
+	pub fn seal_status(&self) -> Result<SealStatus> {
+		self.send(self.request("GET", "sys/seal-status", None, String::new())?)
+	}
+
+	/// Return asynchronously the seal status of the vault server (no login required)
+	pub async fn seal_status_async(&self) -> Result<SealStatus> {
+		self.send_async(self.request("GET", "sys/seal-status", None, String::new())?)
+			.await
+	}
+
+	/// Return the high availability status of the vault server (no login required)
+	pub fn leader(&self) -> Result<Leader> {
+		self.send(self.request("GET", "sys/leader", None, String::new())?)
+	}
+
+	/// Return asynchronously the high availability status of the vault server (no login required)
+	pub async fn leader_async(&self) -> Result<Leader> {
+		self.send_async(self.request("GET", "sys/leader", None, String::new())?)
+			.await
+	}
+
+	/// Block until the vault server is reachable, initialized and unsealed or until timeout
+	pub fn wait_until_ready(&self, timeout: Duration) -> Result<Health> {
+		let start = Instant::now();
+		loop {
+			match self.health() {
+				Ok(health) if health.is_ready() => return Ok(health),
+				Ok(_) => log::debug!("vault server is not ready"),
+				Err(e) => log::debug!("vault server is not reachable: {}", e),
+			}
+			let elapsed = start.elapsed();
+			if elapsed >= timeout {
+				return Err(Error::NotReady(timeout));
+			}
+			std::thread::sleep(READY_POLL_INTERVAL.min(timeout - elapsed));
+		}
+	}
+
+	/// Wait asynchronously until the vault server is reachable, initialized and unsealed or until
+	/// timeout
+	#[cfg(feature = "async-std")]
+	pub async fn wait_until_ready_async(&self, timeout: Duration) -> Result<Health> {
+		let start = Instant::now();
+		loop {
+			match self.health_async().await {
+				Ok(health) if health.is_ready() => return Ok(health),
+				Ok(_) => log::debug!("vault server is not ready"),
+				Err(e) => log::debug!("vault server is not reachable: {}", e),
+			}
+			let elapsed = start.elapsed();
+			if elapsed >= timeout {
+				return Err(Error::NotReady(timeout));
+			}
+			async_std::task::sleep(READY_POLL_INTERVAL.min(timeout - elapsed)).await;
+		}
+	}
+
+	/// Build the request for revoking a token
+	fn revoke_request(&self, token: &str) -> Result<Request<String>> {
+		self.request("POST", "auth/token/revoke-self", Some(token), String::new())
+	}
+
+	/// Revoke the token associated to role and remove it from the cache
+	pub fn revoke_self(&mut self, role: &str) -> Result<()> {
+		self.send::<Value>(self.revoke_request(self.token(role)?)?)?;
+		self.auth.remove(role);
+		self.forget(role);
+		self.uncache_role(role);
+		Ok(())
+	}
+
+	/// Revoke asynchronously the token associated to role and remove it from the cache
+	pub async fn revoke_self_async(&mut self, role: &str) -> Result<()> {
+		self.send_async::<Value>(self.revoke_request(self.token(role)?)?)
+			.await?;
+		self.auth.remove(role);
+		self.forget(role);
+		self.uncache_role(role);
+		Ok(())
+	}
+
+	/// Remove the token associated to role from the cache and revoke it if still valid.
+	/// Batch tokens, which vault cannot revoke, are only dropped. Logging out a role that is
+	/// not logged in is not an error
+	pub fn logout(&mut self, role: &str) -> Result<()> {
+		self.forget(role);
+		self.uncache_role(role);
+		match self.auth.remove(role) {
+			Some(auth) if auth.is_valid() && auth.token_type == TokenType::Service => self
+				.send::<Value>(self.revoke_request(auth.client_token.expose())?)
+				.map(|_| ()),
+			_ => Ok(()),
+		}
+	}
+
+	/// Remove asynchronously the token associated to role from the cache and revoke it if still
+	/// valid. Batch tokens, which vault cannot revoke, are only dropped. Logging out a role that
+	/// is not logged in is not an error
+	pub async fn logout_async(&mut self, role: &str) -> Result<()> {
+		self.forget(role);
+		self.uncache_role(role);
+		match self.auth.remove(role) {
+			Some(auth) if auth.is_valid() && auth.token_type == TokenType::Service => self
+				.send_async::<Value>(self.revoke_request(auth.client_token.expose())?)
+				.await
+				.map(|_| ()),
+			_ => Ok(()),
+		}
+	}
+
+	/// Logout from all roles. All tokens are removed from the cache even if a revocation fails,
+	/// in which case the first error is returned
+	pub fn logout_all(&mut self) -> Result<()> {
+		let roles: Vec<String> = self.auth.keys().cloned().collect();
+		let mut res = Ok(());
+		for role in roles.iter() {
+			if let Err(e) = self.logout(role) {
+				log::warn!("unable to revoke token of role {}: {}", role, e);
+				res = res.and(Err(e));
+			}
+		}
+		res
+	}
+
+	/// Logout asynchronously from all roles. All tokens are removed from the cache even if a
+	/// revocation fails, in which case the first error is returned
+	pub async fn logout_all_async(&mut self) -> Result<()> {
+		let roles: Vec<String> = self.auth.keys().cloned().collect();
+		let mut res = Ok(());
+		for role in roles.iter() {
+			if let Err(e) = self.logout_async(role).await {
+				log::warn!("unable to revoke token of role {}: {}", role, e);
+				res = res.and(Err(e));
+			}
+		}
+		res
+	}
+
+	/// Revoke all cached tokens and consume the client
+	pub fn close(mut self) -> Result<()> {
+		self.revoke_on_drop = false;
+		self.logout_all()
+	}
+
+	/// Revoke asynchronously all cached tokens and consume the client, without blocking in
+	/// `drop`
+	pub async fn close_async(mut self) -> Result<()> {
+		self.revoke_on_drop = false;
+		self.logout_all_async().await
+	}
+
+	/// Read the jwt and return its claims decoded without verifying its signature. Can be used
+	/// to log the jwt identity or to choose a role
+	pub fn claims(&self) -> Result<Claims> {
+		Claims::decode(self.jwt.read()?.expose())
+	}
+
+	/// Set the source of the jwt which is read again before each login
+	pub fn set_jwt_source(&mut self, source: JwtSource) {
+		self.jwt = source;
+	}
+
+	/// Set the policy used to schedule the renewal of tokens and secrets obtained afterwards
+	pub fn set_renew_policy(&mut self, policy: RenewPolicy) {
+		self.renew_policy = policy;
+	}
+
+	/// Continue the given trace in the requests sent to vault outside of a context entered with
+	/// `TraceContext::enter`, `in_scope` or `instrument`, which is the way to give each call
+	/// its own parent (typically parsed from the traceparent header of an incoming request).
+	/// A new trace is started for each request without parent
+	#[cfg(feature = "tracing")]
+	pub fn set_trace_context(&mut self, context: Option<TraceContext>) {
+		self.trace_context = context;
+	}
+
+	/// return the parent of the requests: the entered context or else the one of the client
+	#[cfg(feature = "tracing")]
+	fn trace_parent(&self) -> Option<TraceContext> {
+		TraceContext::current().or(self.trace_context)
+	}
+
+	/// Persist tokens in store and reuse its still valid tokens instead of logging in. A stored
+	/// token is checked with `auth/token/lookup-self` at the first login of its role and a new
+	/// login is done if it was revoked
+	#[cfg(all(unix, feature = "token-store"))]
+	pub fn set_token_store(&mut self, store: TokenStore) -> Result<()> {
+		for (role, auth) in store.load(&self.renew_policy)? {
+			if !self.auth.contains_key(&role) {
+				self.unverified.insert(role.clone());
+				self.auth.insert(role, auth);
+			}
+		}
+		self.token_store = Some(store);
+		Ok(())
+	}
+
+	/// Save the token of role in the store if any
+	#[cfg(all(unix, feature = "token-store"))]
+	fn persist(&self, role: &str) {
+		if let (Some(store), Some(auth)) = (self.token_store.as_ref(), self.auth.get(role)) {
+			if let Err(e) = store.save(role, auth) {
+				log::warn!("unable to store the token of role {}: {}", role, e);
+			}
+		}
+	}
+
+	/// Remove the token of role from the store if any
+	#[cfg(all(unix, feature = "token-store"))]
+	fn forget(&mut self, role: &str) {
+		self.unverified.remove(role);
+		if let Some(ref store) = self.token_store {
+			if let Err(e) = store.remove(role) {
+				log::warn!("unable to remove the token of role {}: {}", role, e);
+			}
+		}
+	}
+
+	/// Check a token loaded from the store and forget it if it was revoked
+	#[cfg(all(unix, feature = "token-store"))]
+	fn verify_stored(&mut self, role: &str) {
+		if self.unverified.remove(role) {
+			match self.lookup_self(role) {
+				Err(e @ Error::VaultError(StatusCode::FORBIDDEN, _)) => {
+					log::warn!("discarding the stored token of role {}: {}", role, e);
+					self.auth.remove(role);
+					self.forget(role);
 				}
-			} else {
-				// parse vault error
-				let errors: VaultErrors =
-					res.json().map_err(|e| Error::ParseError { source: e })?;
-				Err(Error::VaultError(status, errors.errors.join("\n")))
-			};
+				// vault is unreachable or unavailable: check again at the next login
+				Err(e) => {
+					log::warn!("unable to check the stored token of role {}: {}", role, e);
+					self.unverified.insert(role.to_owned());
+				}
+				Ok(_) => (),
+			}
+		}
+	}
+
+	/// Check asynchronously a token loaded from the store and forget it if it was revoked
+	#[cfg(all(unix, feature = "token-store"))]
+	async fn verify_stored_async(&mut self, role: &str) {
+		if self.unverified.remove(role) {
+			match self.lookup_self_async(role).await {
+				Err(e @ Error::VaultError(StatusCode::FORBIDDEN, _)) => {
+					log::warn!("discarding the stored token of role {}: {}", role, e);
+					self.auth.remove(role);
+					self.forget(role);
+				}
+				// vault is unreachable or unavailable: check again at the next login
+				Err(e) => {
+					log::warn!("unable to check the stored token of role {}: {}", role, e);
+					self.unverified.insert(role.to_owned());
+				}
+				Ok(_) => (),
+			}
+		}
+	}
+
+	#[cfg(not(all(unix, feature = "token-store")))]
+	fn persist(&self, _role: &str) {}
+
+	#[cfg(not(all(unix, feature = "token-store")))]
+	fn forget(&mut self, _role: &str) {}
+
+	#[cfg(not(all(unix, feature = "token-store")))]
+	fn verify_stored(&mut self, _role: &str) {}
+
+	#[cfg(not(all(unix, feature = "token-store")))]
+	async fn verify_stored_async(&mut self, _role: &str) {}
+
+	/// Send requests without token when not logged in, for a vault agent with auto-auth to
+	/// inject its own token. `resolve` doesn't log in either in agent mode
+	pub fn set_agent_mode(&mut self, agent_mode: bool) {
+		self.agent_mode = agent_mode;
+	}
+
+	/// Add a middleware run around every request sent afterwards. The `before` hooks run in the
+	/// order the middlewares were added and the `after` hooks in the reverse order
+	pub fn add_middleware<M: Middleware + 'static>(&mut self, middleware: M) {
+		self.middlewares.push(Arc::new(middleware));
+	}
+
+	/// Revoke all cached tokens when the client goes out of scope (disabled by default).
+	/// The revocation is a blocking call made from `drop`: it stalls the executor thread when
+	/// the client is dropped inside an async runtime and can last up to the request timeout of
+	/// every node when failing over. Prefer an explicit `close` or `close_async` in that case
+	pub fn set_revoke_on_drop(&mut self, revoke: bool) {
+		self.revoke_on_drop = revoke;
+	}
+
+	/// Build the request for creating a child or orphan token
+	fn create_token_request(&self, role: &str, params: &TokenRequest) -> Result<Request<String>> {
+		let path = if params.orphan {
+			"auth/token/create-orphan"
 		} else {
+			"auth/token/create"
+		};
+		let mut body = json!({
+			"policies": params.policies,
+			"meta": params.meta,
+			"num_uses": params.num_uses,
+		});
+		if let Some(ttl) = params.ttl {
+			body["ttl"] = format!("{}s", ttl.as_secs()).into();
+		}
+		if let Some(max_ttl) = params.explicit_max_ttl {
+			body["explicit_max_ttl"] = format!("{}s", max_ttl.as_secs()).into();
+		}
+		if let Some(ref name) = params.display_name {
+			body["display_name"] = name.as_str().into();
+		}
+		if let Some(renewable) = params.renewable {
+			body["renewable"] = renewable.into();
+		}
+		self.request("POST", path, self.request_token(role)?, body.to_string())
+	}
+
+	/// Create a child (or orphan) token with the token associated to role.
+	/// The new token is not cached and is returned to the caller
+	pub fn create_token(&self, role: &str, params: &TokenRequest) -> Result<Auth> {
+		let res: AuthResponse = self.send(self.create_token_request(role, params)?)?;
+		Ok(res.auth.into_auth(&self.renew_policy))
+	}
+
+	/// Create asynchronously a child (or orphan) token with the token associated to role.
+	/// The new token is not cached and is returned to the caller
+	pub async fn create_token_async(&self, role: &str, params: &TokenRequest) -> Result<Auth> {
+		let res: AuthResponse = self
+			.send_async(self.create_token_request(role, params)?)
+			.await?;
+		Ok(res.auth.into_auth(&self.renew_policy))
+	}
+}
+
+impl Drop for VaultClient {
+	fn drop(&mut self) {
+		if self.revoke_on_drop {
+			let _ = self.logout_all();
+		}
+	}
+}
+
+/// Build a request to the vault api of a node with an optional token
+fn request_to(
+	url: &str,
+	method: &str,
+	path: &str,
+	token: Option<&str>,
+	body: String,
+) -> Result<Request<String>> {
+	let mut builder = builder_to(url, path).method(method);
+	if let Some(token) = token {
+		let mut token =
+			HeaderValue::from_str(token).map_err(|e| Error::HttpError { source: e.into() })?;
+		// kept out of debug output and header compression tables
+		token.set_sensitive(true);
+		builder = builder.header("X-Vault-Token", token);
+	}
+	builder
+		.body(body)
+		.map_err(|e| Error::HttpError { source: e })
+}
+
+/// Return the path of a request relative to the api of the node at url, or None if the request
+/// doesn't target this node (like a request rewritten by a middleware)
+fn relative_path(url: &str, request: &Request<String>) -> Option<String> {
+	request
+		.uri()
+		.to_string()
+		.strip_prefix(&api_base(url).0)
+		.map(str::to_owned)
+}
+
+/// Split the address of a node into the base uri of the api and the path of its unix socket
+/// if the address is like `unix:///path/to/agent.sock`
+fn api_base(url: &str) -> (String, Option<&str>) {
+	match url.strip_prefix(UNIX_SCHEME) {
+		Some(socket) => (format!("http://localhost{}", UNIX_API_PATH), Some(socket)),
+		None => (url.to_owned(), None),
+	}
+}
+
+/// Start building a request to a path of the api of a node
+fn builder_to(url: &str, path: &str) -> Builder {
+	let (base, socket) = api_base(url);
+	let builder = Request::builder().uri(format!("{}/{}", base, path.trim_start_matches('/')));
+	match socket {
+		#[cfg(unix)]
+		Some(socket) => builder.dial(isahc::config::Dialer::unix_socket(socket)),
+		_ => builder,
+	}
+}
+
+/// Copy a request so that it can be sent again
+fn copy_request(request: &Request<String>) -> Result<Request<String>> {
+	let mut builder = Request::builder()
+		.method(request.method())
+		.uri(request.uri());
+	if let Some(headers) = builder.headers_mut() {
+		*headers = request.headers().clone();
+	}
+	builder
+		.body(request.body().clone())
+		.map_err(|e| Error::HttpError { source: e })
+}
+
+/// Select the first active node, or else the first ready node, among the probed ones
+fn select_node(healths: impl Iterator<Item = (usize, Option<Health>)>) -> Option<usize> {
+	let mut ready = None;
+	for (i, health) in healths {
+		match health {
+			Some(health) if health.is_active() => return Some(i),
+			Some(health) if health.is_ready() && ready.is_none() => ready = Some(i),
+			_ => (),
+		}
+	}
+	ready
+}
+
+/// Return the path of a request relative to the api address without the query
+fn api_path(url: &str, request: &Request<String>) -> String {
+	let uri = request.uri().to_string();
+	let path = uri.strip_prefix(&api_base(url).0).unwrap_or(&uri);
+	path.split('?')
+		.next()
+		.unwrap_or("")
+		.trim_start_matches('/')
+		.to_owned()
+}
+
+/// Check the status of a response and deserialize its body or the vault errors
+fn parse_response<T: DeserializeOwned>(mut res: Response<Body>) -> Result<T> {
+	let status = res.status();
+	if status == StatusCode::NO_CONTENT {
+		serde_json::from_value(Value::Null).map_err(|e| Error::ParseError { source: e })
+	} else if status.is_success() {
+		res.json().map_err(|e| Error::ParseError { source: e })
+	} else {
+		// parse vault error
+		let errors: VaultErrors = res.json().map_err(|e| Error::ParseError { source: e })?;
+		Err(Error::VaultError(status, errors.errors.join("\n")))
+	}
+}
+
+/// Check the status of an async response and deserialize its body or the vault errors
+async fn parse_response_async<T: DeserializeOwned + Unpin>(
+	mut res: Response<AsyncBody>,
+) -> Result<T> {
+	let status = res.status();
+	if status == StatusCode::NO_CONTENT {
+		serde_json::from_value(Value::Null).map_err(|e| Error::ParseError { source: e })
+	} else if status.is_success() {
+		res.json()
+			.await
+			.map_err(|e| Error::ParseError { source: e })
+	} else {
+		// parse vault error
+		let errors: VaultErrors = res
+			.json()
+			.await
+			.map_err(|e| Error::ParseError { source: e })?;
+		Err(Error::VaultError(status, errors.errors.join("\n")))
+	}
+}
+
+/// Group the vault paths of secret paths by role
+fn paths_by_role<'a>(paths: &[SecretPath<'a, Backend>]) -> Result<Vec<(&'a str, Vec<&'a str>)>> {
+	let mut by_role: Vec<(&str, Vec<&str>)> = Vec::new();
+	for path in paths.iter() {
+		let role = path.role()?;
+		match by_role.iter_mut().find(|(r, _)| *r == role) {
+			Some((_, role_paths)) => role_paths.push(path.path),
+			None => by_role.push((role, vec![path.path])),
+		}
+	}
+	Ok(by_role)
+}
+
+/// Filter the secret paths which method is not allowed by the capabilities of their role
+fn denied<'p, 'a>(
+	paths: &'p [SecretPath<'a, Backend>],
+	caps: &HashMap<&str, HashMap<String, Vec<String>>>,
+) -> Vec<&'p SecretPath<'a, Backend>> {
+	paths
+		.iter()
+		.filter(|path| {
+			!path
+				.role()
+				.ok()
+				.and_then(|role| caps.get(role))
+				.and_then(|caps| caps.get(path.path))
+				.map(|caps| is_allowed(path.method(), caps))
+				.unwrap_or(false)
+		})
+		.collect()
+}
+
+/// Convert a secret response to a Secret (only the data part)
+fn to_secret(mut secret_value: Value, policy: &RenewPolicy) -> Secret {
+	let duration = secret_value
+		.get("lease_duration")
+		.map(|o| o.as_u64().unwrap_or(0u64))
+		.filter(|o| *o != 0u64)
+		.map(Duration::from_secs);
+	let lease_id = secret_value["lease_id"].as_str().unwrap_or("").to_owned();
+	Secret::with_policy(secret_value["data"].take(), duration, policy).with_lease_id(&lease_id)
+}
+
+/// Return a copy of a secret with the lease of a sys/leases/renew response
+fn renewed_secret(secret: &Secret, mut res: Value, policy: &RenewPolicy) -> Secret {
+	res["data"] = secret.value.expose().clone();
+	if res["lease_id"].as_str().unwrap_or("").is_empty() {
+		res["lease_id"] = secret.lease_id().unwrap_or("").into();
+	}
+	to_secret(res, policy)
+}
+
+/// Secret fetched by `resolve_all`, with a shared error as it can be the outcome of many paths
+type Fetched = std::result::Result<Secret, Arc<Error>>;
+
+/// Return the distinct requests of secret paths and, for each path, the index of its request
+fn plan<'p, 'a>(
+	paths: &'p [SecretPath<'a, Backend>],
+) -> (Vec<&'p SecretPath<'a, Backend>>, Vec<Result<usize>>) {
+	let mut index = HashMap::new();
+	let mut fetches = Vec::new();
+	let slots = paths
+		.iter()
+		.map(|path| match path.backend {
+			Backend::Vault => {
+				let key =
+					CacheKey::new(path.role()?, path.method(), path.path, path.kwargs.as_ref());
+				Ok(*index.entry(key).or_insert_with(|| {
+					fetches.push(path);
+					fetches.len() - 1
+				}))
+			}
+		})
+		.collect();
+	(fetches, slots)
+}
+
+/// Return the distinct roles of secret paths
+fn roles<'a>(paths: &[&SecretPath<'a, Backend>]) -> Vec<&'a str> {
+	let mut roles: Vec<_> = paths.iter().filter_map(|path| path.role().ok()).collect();
+	roles.sort_unstable();
+	roles.dedup();
+	roles
+}
+
+/// Select the value of each path in the secret of its request
+fn assemble(
+	paths: &[SecretPath<'_, Backend>],
+	slots: Vec<Result<usize>>,
+	mut fetched: Vec<(usize, Fetched)>,
+) -> Vec<Result<SecretValue>> {
+	fetched.sort_unstable_by_key(|(i, _)| *i);
+	paths
+		.iter()
+		.zip(slots)
+		.map(|(path, slot)| match fetched[slot?].1 {
+			Ok(ref secret) => path.select_secret(secret.value.clone()),
+			Err(ref e) => Err(Error::Resolve {
+				path: path.to_string(),
+				source: e.clone(),
+			}),
+		})
+		.collect()
+}
+
+/// Convert a sys/mounts response to a hashmap of mountpoints and backend types
+fn to_mounts(mounts_value: Value) -> Result<HashMap<String, String>> {
+	if let Some(Value::Object(map)) = mounts_value.get("data") {
+		let mut mounts = HashMap::new();
+		for (mount_point, mount) in map.iter() {
+			let mount_type = mount
+				.get("type")
+				.and_then(|v| v.as_str())
+				.ok_or_else(|| Error::UndefinedMountType(mount_point.to_owned()))?;
+			let mount_version = mount
+				.get("options")
+				.and_then(|v| v.get("version"))
+				.and_then(|v| v.as_str());
+			mounts.insert(
+				mount_point.to_owned(),
+				mount_version
+					.map(|s| mount_type.to_owned() + s)
+					.unwrap_or_else(|| mount_type.to_owned()),
+			);
+		}
+		Ok(mounts)
+	} else {
+		Err(Error::MountsNotFound)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::mock::MockVault;
+	use std::convert::TryFrom;
+
+	fn health(initialized: bool, sealed: bool, standby: bool) -> Option<Health> {
+		Some(Health {
+			initialized,
+			sealed,
+			standby,
+			..Default::default()
+		})
+	}
+
+	#[test]
+	fn parse_addresses() {
+		let client = VaultClient::new(
+			"https://node1:8200/v1, https://node2:8200/v1/",
+			"/auth/jwt/login",
+			"jwt",
+			None,
+		)
+		.unwrap();
+		assert_eq!(
+			client.urls,
+			vec!["https://node1:8200/v1", "https://node2:8200/v1"]
+		);
+		assert_eq!(client.url(), "https://node1:8200/v1");
+	}
+
+	#[test]
+	fn select_active_node() {
+		let healths = vec![
+			(0, None),
+			(1, health(true, false, true)),
+			(2, health(true, false, false)),
+		];
+		assert_eq!(select_node(healths.into_iter()), Some(2));
+	}
+
+	#[test]
+	fn select_standby_node() {
+		let healths = vec![
+			(0, health(true, true, false)),
+			(1, health(true, false, true)),
+			(2, None),
+		];
+		assert_eq!(select_node(healths.into_iter()), Some(1));
+	}
+
+	/// a mock vault with an `app` role and a kv2 secret
+	fn mock_vault() -> MockVault {
+		let vault = MockVault::start();
+		vault.add_role("app", &["app"]);
+		vault.add_kv2("secret", "app", json!({"user": "admin", "pass": "secret"}));
+		vault
+	}
+
+	fn mock_client(vault: &MockVault) -> VaultClient {
+		VaultClient::new(vault.url(), "/auth/jwt/login", &vault.jwt(), None).unwrap()
+	}
+
+	#[test]
+	fn login_and_get_secret() {
+		let vault = mock_vault();
+		vault.add_kv1("kv", "db", json!({"port": 5432}));
+		let mut client = mock_client(&vault);
+		assert!(client.login("app").unwrap().is_valid());
+		let secret = client
+			.get_secret("app", "GET", "secret/data/app", None)
+			.unwrap();
+		assert_eq!(secret.value.expose()["data"]["user"], "admin");
+		assert!(!secret.has_lease());
+		let path = SecretPath::try_from("vault:app:kv/db#/port").unwrap();
+		assert_eq!(*client.resolve(&path).unwrap().expose(), json!(5432));
+		assert!(matches!(
+			client.login("unknown"),
+			Err(Error::VaultError(StatusCode::BAD_REQUEST, _))
+		));
+	}
+
+	#[test]
+	fn renew_then_revoke_token() {
+		let vault = mock_vault();
+		let mut client = mock_client(&vault);
+		// schedule the renewal immediately
+		client.set_renew_policy(RenewPolicy {
+			fraction: 0.0,
+			..Default::default()
+		});
+		let token = client.login("app").unwrap().client_token.clone();
+		assert_eq!(client.login("app").unwrap().client_token, token);
+		assert!(vault
+			.requests()
+			.contains(&"POST auth/token/renew-self".to_owned()));
+		assert_eq!(client.lookup_self("app").unwrap().policies, vec!["app"]);
+		client.logout_all().unwrap();
+		assert!(!vault.is_token_valid(token.expose()));
+		assert!(matches!(
+			client.get_secret("app", "GET", "secret/data/app", None),
 			Err(Error::NotLogged)
+		));
+	}
+
+	#[test]
+	fn renew_leased_secret() {
+		let vault = mock_vault();
+		vault.add_leased_secret(
+			"database/creds/app",
+			json!({"username": "v-app", "password": "secret"}),
+			Duration::from_secs(60),
+		);
+		let mut client = mock_client(&vault);
+		client.login("app").unwrap();
+		let secret = client
+			.get_secret("app", "GET", "database/creds/app", None)
+			.unwrap();
+		assert_eq!(secret.duration(), Some(Duration::from_secs(60)));
+		let renewed = client.renew_secret("app", &secret).unwrap();
+		assert_eq!(renewed.lease_id(), secret.lease_id());
+		assert_eq!(renewed.value, secret.value);
+	}
+
+	#[test]
+	fn cache_and_injected_errors() {
+		let vault = mock_vault();
+		let mut client = mock_client(&vault);
+		client.enable_cache(false);
+		client.login("app").unwrap();
+		vault.fail_next("secret/data/app", 500, "internal error");
+		assert!(matches!(
+			client.get_secret("app", "GET", "secret/data/app", None),
+			Err(Error::VaultError(StatusCode::INTERNAL_SERVER_ERROR, _))
+		));
+		for _ in 0..2 {
+			client
+				.get_secret("app", "GET", "secret/data/app", None)
+				.unwrap();
+		}
+		assert_eq!(client.cache_stats().unwrap().hits, 1);
+		client.logout("app").unwrap();
+		assert!(matches!(
+			client.get_secret("app", "GET", "secret/data/app", None),
+			Err(Error::NotLogged)
+		));
+		assert_eq!(client.cache_stats().unwrap().entries, 0);
+		let reads = vault
+			.requests()
+			.iter()
+			.filter(|r| *r == "GET secret/data/app")
+			.count();
+		assert_eq!(reads, 2);
+	}
+
+	#[test]
+	fn mounts_and_capabilities() {
+		let vault = mock_vault();
+		vault.set_capabilities("secret/data/other", &["deny"]);
+		let mut client = mock_client(&vault);
+		client.login("app").unwrap();
+		assert_eq!(
+			client.get_mounts("app").unwrap().get("secret/"),
+			Some(&"kv2".to_owned())
+		);
+		vault.add_role("ci", &["ci"]);
+		client.login("ci").unwrap();
+		let paths: Vec<_> = [
+			"vault:app:secret/data/app",
+			"vault:app:secret/data/other",
+			"vault:ci,DELETE:secret/data/other",
+		]
+		.iter()
+		.map(|p| SecretPath::try_from(*p).unwrap())
+		.collect();
+		let denied = client.denied_paths(&paths).unwrap();
+		assert_eq!(denied, vec![&paths[1], &paths[2]]);
+		let requests = vault.requests();
+		let checks = requests
+			.iter()
+			.filter(|r| *r == "POST sys/capabilities-self")
+			.count();
+		assert_eq!(checks, 2);
+		let denied = futures_lite::future::block_on(client.denied_paths_async(&paths[..1]));
+		assert!(denied.unwrap().is_empty());
+	}
+
+	#[test]
+	fn failover_and_health() {
+		let vault = mock_vault();
+		// nothing listens on port 1
+		let url = format!("http://127.0.0.1:1/v1,{}", vault.url());
+		let mut client = VaultClient::new(&url, "/auth/jwt/login", &vault.jwt(), None).unwrap();
+		client.login("app").unwrap();
+		assert_eq!(client.url(), vault.url());
+		assert!(client.health().unwrap().is_ready());
+		vault.set_sealed(true);
+		assert!(matches!(
+			client.wait_until_ready(Duration::from_millis(100)),
+			Err(Error::NotReady(_))
+		));
+	}
+
+	#[test]
+	fn failover_of_a_request_built_before_a_switch() {
+		let vault = mock_vault();
+		let url = format!("http://127.0.0.1:1/v1,{}", vault.url());
+		let client = VaultClient::new(&url, "/auth/jwt/login", &vault.jwt(), None).unwrap();
+		let request = client
+			.request("GET", "sys/seal-status", None, String::new())
+			.unwrap();
+		// another request already failed over
+		client.active.store(1, Ordering::Relaxed);
+		let status: SealStatus = client.send(request).unwrap();
+		assert!(!status.sealed);
+		assert_eq!(client.url, url);
+		assert!(vault.requests().contains(&"GET sys/seal-status".to_owned()));
+	}
+
+	#[test]
+	fn login_and_get_secret_async() {
+		let vault = mock_vault();
+		let mut client = mock_client(&vault);
+		futures_lite::future::block_on(async {
+			client.login_async("app").await.unwrap();
+			let secret = client
+				.get_secret_async("app", "GET", "secret/data/app", None)
+				.await
+				.unwrap();
+			assert_eq!(secret.value.expose()["data"]["pass"], "secret");
+			client.logout_all_async().await.unwrap();
+		});
+		assert_eq!(vault.token_count(), 0);
+	}
+
+	#[test]
+	fn logout_drops_batch_tokens() {
+		let vault = mock_vault();
+		vault.set_batch_tokens(true);
+		let mut client = mock_client(&vault);
+		client.login("app").unwrap();
+		client.logout_all().unwrap();
+		assert!(!client.is_logged("app"));
+		assert!(!vault
+			.requests()
+			.iter()
+			.any(|r| r == "POST auth/token/revoke-self"));
+		futures_lite::future::block_on(async {
+			client.login_async("app").await.unwrap();
+			client.logout_async("app").await.unwrap();
+		});
+	}
+
+	#[test]
+	fn close_revokes_tokens() {
+		let vault = mock_vault();
+		let mut client = mock_client(&vault);
+		client.set_revoke_on_drop(true);
+		futures_lite::future::block_on(async {
+			client.login_async("app").await.unwrap();
+			assert_eq!(vault.token_count(), 1);
+			client.close_async().await.unwrap();
+		});
+		assert_eq!(vault.token_count(), 0);
+		assert_eq!(
+			vault
+				.requests()
+				.iter()
+				.filter(|r| *r == "POST auth/token/revoke-self")
+				.count(),
+			1
+		);
+	}
+
+	#[test]
+	fn resolve_all_paths() {
+		let vault = mock_vault();
+		vault.add_kv1("kv", "db", json!({"port": 5432}));
+		let paths: Vec<_> = [
+			"vault:app:secret/data/app#/data/user",
+			"vault:app:secret/data/app#/data/pass",
+			"vault:app:kv/db#/port",
+			"vault:app:kv/missing",
+			"vault:other:kv/db",
+		]
+		.iter()
+		.map(|p| SecretPath::try_from(*p).unwrap())
+		.collect();
+		let mut client = mock_client(&vault);
+		let values = client.resolve_all(&paths, 4);
+		assert_eq!(values[0].as_ref().unwrap().expose(), "admin");
+		assert_eq!(values[1].as_ref().unwrap().expose(), "secret");
+		assert_eq!(values[2].as_ref().unwrap().expose(), &json!(5432));
+		assert!(matches!(
+			values[3],
+			Err(Error::Resolve { ref source, .. }) if matches!(**source, Error::VaultError(StatusCode::NOT_FOUND, _))
+		));
+		assert!(matches!(values[4], Err(Error::Resolve { .. })));
+		let requests = vault.requests();
+		let count = |request: &str| requests.iter().filter(|r| *r == request).count();
+		assert_eq!(count("GET secret/data/app"), 1);
+		assert_eq!(count("POST auth/jwt/login"), 2);
+		let values = futures_lite::future::block_on(client.resolve_all_async(&paths, 2));
+		assert_eq!(values[2].as_ref().unwrap().expose(), &json!(5432));
+		assert!(values[3].is_err());
+	}
+
+	#[test]
+	fn resolve_all_fails_over() {
+		let vault = mock_vault();
+		let names = ["a", "b", "c", "d"];
+		for name in names.iter() {
+			vault.add_kv1("kv", name, json!({ "name": name }));
 		}
+		let url = format!("http://127.0.0.1:1/v1,{}", vault.url());
+		let mut client = VaultClient::new(&url, "/auth/jwt/login", &vault.jwt(), None).unwrap();
+		client.login("app").unwrap();
+		let paths: Vec<_> = names
+			.iter()
+			.map(|name| format!("vault:app:kv/{}#/name", name))
+			.collect();
+		let paths: Vec<_> = paths
+			.iter()
+			.map(|p| SecretPath::try_from(p.as_str()).unwrap())
+			.collect();
+		// the concurrent requests are all sent to the first node which is down
+		client.active.store(0, Ordering::Relaxed);
+		let values = client.resolve_all(&paths, 4);
+		for (value, name) in values.iter().zip(names.iter()) {
+			assert_eq!(value.as_ref().unwrap().expose(), name);
+		}
+		assert_eq!(client.url(), vault.url());
+		client.active.store(0, Ordering::Relaxed);
+		let values = futures_lite::future::block_on(client.resolve_all_async(&paths, 4));
+		assert!(values.iter().all(Result::is_ok));
+	}
+
+	#[test]
+	fn record_metrics() {
+		let vault = mock_vault();
+		let mut client = mock_client(&vault);
+		assert!(client.login("other").is_err());
+		client.login("app").unwrap();
+		client
+			.get_secret("app", "GET", "secret/data/app", None)
+			.unwrap();
+		let metrics = client.metrics();
+		assert_eq!(metrics.logins["app"], 1);
+		assert_eq!(metrics.login_failures["other"], 1);
+		assert_eq!(
+			metrics.requests[&("auth/jwt/login".to_owned(), "400".to_owned())],
+			1
+		);
+		assert_eq!(metrics.latency["secret/data/app"].count, 1);
+		assert!(metrics.token_expiry["app"] > Duration::from_secs(3000));
+	}
+
+	/// route requests through a proxy and tag them
+	struct Proxy {
+		url: String,
+		after: Arc<Mutex<Vec<Option<StatusCode>>>>,
+	}
+
+	impl Middleware for Proxy {
+		fn before(&self, request: &mut Request<String>) -> Result<()> {
+			let uri = request.uri().to_string().replace("http://proxy", &self.url);
+			*request.uri_mut() = uri.parse().unwrap();
+			request
+				.headers_mut()
+				.insert("X-Vault-Request", "true".parse().unwrap());
+			Ok(())
+		}
+
+		fn after(&self, exchange: &Exchange<'_>) {
+			self.after.lock().unwrap().push(exchange.status);
+		}
+	}
+
+	#[test]
+	fn middleware_rewrites_requests() {
+		let vault = mock_vault();
+		let mut client =
+			VaultClient::new("http://proxy/v1", "/auth/jwt/login", &vault.jwt(), None).unwrap();
+		let after = Arc::new(Mutex::new(Vec::new()));
+		client.add_middleware(Proxy {
+			url: vault.url().trim_end_matches("/v1").to_owned(),
+			after: after.clone(),
+		});
+		client.login("app").unwrap();
+		assert_eq!(
+			vault.last_header("X-Vault-Request").as_deref(),
+			Some("true")
+		);
+		assert!(client
+			.get_secret("app", "GET", "secret/data/none", None)
+			.is_err());
+		assert_eq!(
+			*after.lock().unwrap(),
+			vec![Some(StatusCode::OK), Some(StatusCode::NOT_FOUND)]
+		);
+		assert_eq!(client.metrics().logins["app"], 1);
+	}
+
+	#[test]
+	fn debug_is_redacted() {
+		let vault = mock_vault();
+		let mut client = mock_client(&vault);
+		client.enable_cache(false);
+		let token = client.login("app").unwrap().client_token.clone();
+		client
+			.get_secret("app", "GET", "secret/data/app", None)
+			.unwrap();
+		let debug = format!("{:?}", client);
+		assert!(!debug.contains(token.expose()));
+		assert!(!debug.contains("admin"));
+	}
+
+	#[cfg(feature = "tracing")]
+	#[test]
+	fn requests_continue_the_entered_trace() {
+		let vault = mock_vault();
+		let mut client = mock_client(&vault);
+		let trace_of_last_request = || {
+			let header = vault.last_header(trace::TRACEPARENT).unwrap();
+			TraceContext::parse(&header).unwrap().trace_id
+		};
+		let (default, call) = (TraceContext::random(), TraceContext::random());
+		client.set_trace_context(Some(default));
+		client.login("app").unwrap();
+		assert_eq!(trace_of_last_request(), default.trace_id);
+		call.in_scope(|| client.get_secret("app", "GET", "secret/data/app", None))
+			.unwrap();
+		assert_eq!(trace_of_last_request(), call.trace_id);
+		client.set_trace_context(None);
+		assert!(client
+			.get_secret("app", "GET", "secret/data/none", None)
+			.is_err());
+		let random = trace_of_last_request();
+		assert!(random != default.trace_id && random != call.trace_id);
+	}
+
+	#[cfg(all(unix, feature = "token-store"))]
+	#[test]
+	fn reuse_stored_token() {
+		let vault = mock_vault();
+		let dir =
+			std::env::temp_dir().join(format!("vault-jwt-client-store-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let store = TokenStore::new(dir.join("tokens"), dir.join("key"));
+		let logins = || {
+			vault
+				.requests()
+				.iter()
+				.filter(|r| *r == "POST auth/jwt/login")
+				.count()
+		};
+		let mut client = mock_client(&vault);
+		client.set_token_store(store.clone()).unwrap();
+		let token = client.login("app").unwrap().client_token.clone();
+		// a new process reuses the stored token
+		let mut client = mock_client(&vault);
+		client.set_token_store(store.clone()).unwrap();
+		assert_eq!(client.login("app").unwrap().client_token, token);
+		assert_eq!(logins(), 1);
+		// keeps it while vault is unavailable
+		vault.set_sealed(true);
+		let mut client = mock_client(&vault);
+		client.set_token_store(store.clone()).unwrap();
+		assert_eq!(client.login("app").unwrap().client_token, token);
+		vault.set_sealed(false);
+		assert!(store
+			.load(&RenewPolicy::default())
+			.unwrap()
+			.contains_key("app"));
+		// and logs in again if it was revoked
+		vault.revoke_token(token.expose());
+		let mut client = mock_client(&vault);
+		client.set_token_store(store).unwrap();
+		assert_ne!(client.login("app").unwrap().client_token, token);
+		assert_eq!(logins(), 2);
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[cfg(not(unix))]
+	#[test]
+	fn unix_socket_is_an_error() {
+		assert!(matches!(
+			VaultClient::new("http://vault/v1,unix:///run/agent.sock", "", "", None),
+			Err(Error::UnsupportedSocket(url)) if url == "unix:///run/agent.sock"
+		));
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn agent_on_unix_socket() {
+		let socket =
+			std::env::temp_dir().join(format!("vault-jwt-agent-{}.sock", std::process::id()));
+		let _ = std::fs::remove_file(&socket);
+		let vault = MockVault::start_unix(&socket);
+		vault.add_role("app", &["app"]);
+		vault.add_kv2("secret", "app", json!({"user": "admin"}));
+		let mut client = mock_client(&vault);
+		assert_eq!(client.health().unwrap().version, "1.9.0");
+		assert!(matches!(
+			client.get_secret("app", "GET", "secret/data/app", None),
+			Err(Error::NotLogged)
+		));
+		// the agent injects its token
+		vault.enable_agent("app");
+		client.set_agent_mode(true);
+		let path = SecretPath::try_from("vault:app:secret/data/app#/data/user").unwrap();
+		assert_eq!(*client.resolve(&path).unwrap().expose(), json!("admin"));
+		assert!(client.auth.is_empty());
+		assert_eq!(client.metrics().requests.len(), 2);
+		std::fs::remove_file(&socket).unwrap();
 	}
 }
@@ -2,6 +2,7 @@ use isahc::http::StatusCode;
 #[cfg(feature = "nom")]
 use nom::error::ErrorKind;
 use serde::Deserialize;
+use std::time::Duration;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -9,6 +10,12 @@ pub type Result<T> = std::result::Result<T, Error>;
 pub enum Error {
 	#[error("not logged to vault server")]
 	NotLogged,
+	#[error("invalid jwt: {0}")]
+	InvalidJwt(String),
+	#[error("jwt expired at {0} (seconds since epoch)")]
+	JwtExpired(u64),
+	#[error("jwt not valid before {0} (seconds since epoch)")]
+	JwtNotYetValid(u64),
 	#[error("http error code {0}\n{1}")]
 	VaultError(StatusCode, String),
 	#[error("token error")]
@@ -16,6 +23,11 @@ pub enum Error {
 		#[from]
 		source: std::io::Error,
 	},
+	#[error("unable to read the jwt from {src}")]
+	JwtSourceError {
+		src: String,
+		source: std::io::Error,
+	},
 	#[error(transparent)]
 	HttpError {
 		#[from]
@@ -31,12 +43,59 @@ pub enum Error {
 		#[from]
 		source: serde_json::error::Error,
 	},
+	#[error("no vault address given")]
+	NoAddress,
+	#[error("no vault node is ready")]
+	NoActiveNode,
+	#[error("vault server not ready after {0:?}")]
+	NotReady(Duration),
+	#[error("the secret has no lease")]
+	NoLease,
 	#[error("unable to get vault mount")]
 	MountsNotFound,
 	#[error("undefined mount type {0}")]
 	UndefinedMountType(String),
 	#[error("unknown backend \"{0}\"")]
 	UnknowBackend(String),
+	#[error("missing the role argument in \"{0}\"")]
+	NoRole(String),
+	#[error("anchor \"{0}\" not found in secret \"{1}\"")]
+	AnchorNotFound(String, String),
+	#[error("line {line}, column {col}")]
+	Template {
+		line: usize,
+		col: usize,
+		source: Box<Error>,
+	},
+	#[error("unclosed placeholder at line {0}, column {1}")]
+	UnclosedPlaceholder(usize, usize),
+	#[error("i/o error on {path}")]
+	IoError {
+		path: String,
+		source: std::io::Error,
+	},
+	#[error("missing a \"=\" to separate the variable name from the secret path \"{0}\"")]
+	NoVariable(String),
+	#[error("invalid variable name \"{0}\"")]
+	InvalidName(String),
+	#[error("unable to execute {command}")]
+	ExecError {
+		command: String,
+		source: std::io::Error,
+	},
+	#[error("hook \"{0}\" failed with {1}")]
+	HookFailed(String, std::process::ExitStatus),
+	#[error("request aborted by middleware: {0}")]
+	Middleware(String),
+	#[error("token store error: {0}")]
+	TokenStore(String),
+	#[error("unable to resolve \"{path}\"")]
+	Resolve {
+		path: String,
+		source: std::sync::Arc<Error>,
+	},
+	#[error("unix socket address \"{0}\" not supported on this platform")]
+	UnsupportedSocket(String),
 	#[error("missing the backend argument")]
 	NoBackend,
 	#[error("missing a \":\" to separate backend from arguments \"{0}\"")]
@@ -0,0 +1,335 @@
+use crate::{
+	client::VaultClient,
+	error::{Error, Result},
+	secret::{value_to_string, Backend, SecretPath},
+};
+
+use libc::{c_int, SIGHUP, SIGINT, SIGQUIT, SIGTERM, SIGUSR1, SIGUSR2};
+use serde_json::Value;
+use std::{
+	convert::TryFrom,
+	io, mem,
+	process::{Command, ExitStatus},
+	ptr,
+	sync::{
+		atomic::{AtomicI32, Ordering},
+		Mutex,
+	},
+};
+
+/// Signals forwarded to the child process
+const FORWARDED_SIGNALS: [c_int; 6] = [SIGHUP, SIGINT, SIGQUIT, SIGTERM, SIGUSR1, SIGUSR2];
+/// Signals of the terminal which already reach the child when the current process is in the
+/// foreground process group of the terminal, so they are not forwarded a second time
+const TERMINAL_SIGNALS: [c_int; 2] = [SIGINT, SIGQUIT];
+
+/// Pid of the child process signals are forwarded to (0 when not spawned yet or exited)
+static CHILD: AtomicI32 = AtomicI32::new(0);
+/// Signal received before the child was spawned (0 if none)
+static PENDING: AtomicI32 = AtomicI32::new(0);
+/// Serialize the executions as there is a single set of signal handlers
+static EXEC: Mutex<()> = Mutex::new(());
+
+/// Rules to flatten nested secret values into environment variables
+#[derive(Debug, Clone)]
+pub struct FlattenRules {
+	/// separator between the variable name and the keys of an object
+	pub separator: String,
+	/// convert the keys of objects to upper case
+	pub uppercase: bool,
+	/// flatten arrays with the index as key instead of serializing them to json
+	pub flatten_arrays: bool,
+}
+
+impl Default for FlattenRules {
+	/// NAME_SUBKEY with arrays serialized to json
+	fn default() -> Self {
+		Self {
+			separator: "_".to_owned(),
+			uppercase: true,
+			flatten_arrays: false,
+		}
+	}
+}
+
+/// Flatten a secret value into environment variables. Scalars are exported as is under name and
+/// objects are exported as one variable per key named `NAME_SUBKEY`
+pub fn flatten(name: &str, value: &Value, rules: &FlattenRules) -> Vec<(String, String)> {
+	let mut vars = Vec::new();
+	flatten_into(name.to_owned(), value, rules, &mut vars);
+	vars
+}
+
+fn flatten_into(
+	name: String,
+	value: &Value,
+	rules: &FlattenRules,
+	vars: &mut Vec<(String, String)>,
+) {
+	match value {
+		Value::Object(map) => {
+			for (key, value) in map.iter() {
+				flatten_into(sub_name(&name, key, rules), value, rules, vars);
+			}
+		}
+		Value::Array(array) if rules.flatten_arrays => {
+			for (i, value) in array.iter().enumerate() {
+				flatten_into(sub_name(&name, &i.to_string(), rules), value, rules, vars);
+			}
+		}
+		Value::Null => vars.push((name, String::new())),
+		value => vars.push((name, value_to_string(value))),
+	}
+}
+
+/// Build the name of the variable of a sub key keeping only characters valid in shells
+fn sub_name(name: &str, key: &str, rules: &FlattenRules) -> String {
+	let key: String = key
+		.chars()
+		.map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+		.collect();
+	let key = if rules.uppercase {
+		key.to_ascii_uppercase()
+	} else {
+		key
+	};
+	format!("{}{}{}", name, rules.separator, key)
+}
+
+/// Split a `NAME=backend:args:path` mapping into the variable name and the secret path
+pub fn parse_mapping(mapping: &str) -> Result<(&str, SecretPath<'_, Backend>)> {
+	let pos = mapping
+		.find('=')
+		.ok_or_else(|| Error::NoVariable(mapping.to_owned()))?;
+	Ok((&mapping[..pos], SecretPath::try_from(&mapping[pos + 1..])?))
+}
+
+/// Resolve secrets and return the flattened environment variables
+pub fn resolve_env(
+	client: &mut VaultClient,
+	mapping: &[(&str, SecretPath<'_, Backend>)],
+	rules: &FlattenRules,
+) -> Result<Vec<(String, String)>> {
+	let mut vars = Vec::new();
+	for (name, path) in mapping.iter() {
+		vars.extend(flatten(name, client.resolve(path)?.expose(), rules));
+	}
+	Ok(vars)
+}
+
+/// Resolve the secrets of mapping, inject them only in the environment of command and wait for
+/// its termination while forwarding it the signals received by the current process. The
+/// previous signal dispositions are restored when the command exits
+pub fn exec_with_secrets(
+	client: &mut VaultClient,
+	mapping: &[(&str, SecretPath<'_, Backend>)],
+	mut command: Command,
+	rules: &FlattenRules,
+) -> Result<ExitStatus> {
+	let program = command.get_program().to_string_lossy().into_owned();
+	let exec_error = |e: io::Error| Error::ExecError {
+		command: program.clone(),
+		source: e,
+	};
+	let vars = resolve_env(client, mapping, rules)?;
+	let _lock = EXEC.lock().unwrap_or_else(|e| e.into_inner());
+	// install the handlers before spawning to not miss any signal
+	let handlers = Handlers::install().map_err(exec_error)?;
+	let mut child = command.envs(vars).spawn().map_err(exec_error)?;
+	let pid = child.id() as libc::pid_t;
+	CHILD.store(pid, Ordering::SeqCst);
+	let pending = PENDING.swap(0, Ordering::SeqCst);
+	if pending != 0 {
+		// safe: only sends a signal to our child process
+		unsafe {
+			libc::kill(pid, pending);
+		}
+	}
+	// stop forwarding before reaping the child, whose pid could be reused afterwards
+	let exited = wait_exit(pid);
+	drop(handlers);
+	let status = child.wait().map_err(exec_error)?;
+	exited.map_err(exec_error)?;
+	Ok(status)
+}
+
+/// Signal handlers of the current process while a child is running, restoring the previous
+/// ones when dropped
+struct Handlers {
+	previous: Vec<(c_int, libc::sigaction)>,
+}
+
+impl Handlers {
+	fn install() -> io::Result<Self> {
+		PENDING.store(0, Ordering::SeqCst);
+		let mut handlers = Self {
+			previous: Vec::with_capacity(FORWARDED_SIGNALS.len()),
+		};
+		for signal in FORWARDED_SIGNALS.iter() {
+			// safe: the handler only uses atomics, tcgetpgrp, getpgrp and kill which are
+			// async-signal-safe
+			unsafe {
+				let mut action: libc::sigaction = mem::zeroed();
+				action.sa_sigaction = forward as extern "C" fn(c_int) as libc::sighandler_t;
+				action.sa_flags = libc::SA_RESTART;
+				libc::sigemptyset(&mut action.sa_mask);
+				let mut previous: libc::sigaction = mem::zeroed();
+				if libc::sigaction(*signal, &action, &mut previous) != 0 {
+					return Err(io::Error::last_os_error());
+				}
+				handlers.previous.push((*signal, previous));
+			}
+		}
+		Ok(handlers)
+	}
+}
+
+impl Drop for Handlers {
+	fn drop(&mut self) {
+		CHILD.store(0, Ordering::SeqCst);
+		for (signal, previous) in self.previous.iter() {
+			// safe: restores a disposition returned by sigaction
+			unsafe {
+				libc::sigaction(*signal, previous, ptr::null_mut());
+			}
+		}
+	}
+}
+
+/// Forward a signal to the child process, or keep it until the child is spawned
+extern "C" fn forward(signal: c_int) {
+	if TERMINAL_SIGNALS.contains(&signal) && in_foreground() {
+		return;
+	}
+	let mut pid = CHILD.load(Ordering::SeqCst);
+	if pid == 0 {
+		PENDING.store(signal, Ordering::SeqCst);
+		// the child may have been spawned in between
+		pid = CHILD.load(Ordering::SeqCst);
+		if pid == 0 || PENDING.swap(0, Ordering::SeqCst) == 0 {
+			return;
+		}
+	}
+	// safe: only sends a signal to our child process
+	unsafe {
+		libc::kill(pid, signal);
+	}
+}
+
+/// Check if the current process (and so its child) is in the foreground process group of the
+/// terminal, which receives the signals typed on the terminal
+fn in_foreground() -> bool {
+	// safe: only reads the process groups
+	unsafe { libc::tcgetpgrp(libc::STDIN_FILENO) == libc::getpgrp() }
+}
+
+/// Wait for the exit of a child process without reaping it
+fn wait_exit(pid: libc::pid_t) -> io::Result<()> {
+	loop {
+		// safe: info is only written by waitid
+		let res = unsafe {
+			let mut info: libc::siginfo_t = mem::zeroed();
+			libc::waitid(
+				libc::P_PID,
+				pid as libc::id_t,
+				&mut info,
+				libc::WEXITED | libc::WNOWAIT,
+			)
+		};
+		if res == 0 {
+			return Ok(());
+		}
+		let e = io::Error::last_os_error();
+		if e.kind() != io::ErrorKind::Interrupted {
+			return Err(e);
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use serde_json::json;
+
+	#[test]
+	fn flatten_nested_objects() {
+		let value = json!({"user": "admin", "db": {"pass-word": "secret", "port": 5432}, "tags": ["a", "b"]});
+		let mut vars = flatten("APP", &value, &FlattenRules::default());
+		vars.sort();
+		assert_eq!(
+			vars,
+			vec![
+				("APP_DB_PASS_WORD".to_owned(), "secret".to_owned()),
+				("APP_DB_PORT".to_owned(), "5432".to_owned()),
+				("APP_TAGS".to_owned(), r#"["a","b"]"#.to_owned()),
+				("APP_USER".to_owned(), "admin".to_owned()),
+			]
+		);
+	}
+
+	#[test]
+	fn flatten_arrays() {
+		let rules = FlattenRules {
+			separator: "__".to_owned(),
+			uppercase: false,
+			flatten_arrays: true,
+		};
+		assert_eq!(
+			flatten("hosts", &json!(["a", "b"]), &rules),
+			vec![
+				("hosts__0".to_owned(), "a".to_owned()),
+				("hosts__1".to_owned(), "b".to_owned())
+			]
+		);
+	}
+
+	#[test]
+	fn exec_injects_env_and_forwards_signals() {
+		let vault = crate::mock::MockVault::start();
+		vault.add_role("app", &["app"]);
+		vault.add_kv2("secret", "app", json!({"user": "admin"}));
+		let mut client =
+			VaultClient::new(vault.url(), "/auth/jwt/login", &vault.jwt(), None).unwrap();
+		let mapping = vec![parse_mapping("APP=vault:app:secret/data/app#/data").unwrap()];
+		let sigterm = |action: *mut libc::sigaction| unsafe {
+			libc::sigaction(SIGTERM, ptr::null(), action);
+		};
+		let mut before: libc::sigaction = unsafe { mem::zeroed() };
+		sigterm(&mut before);
+		let mut command = Command::new("sh");
+		command.args(["-c", r#"test "$APP_USER" = admin && exit 3"#]);
+		let status = exec_with_secrets(&mut client, &mapping, command, &FlattenRules::default());
+		assert_eq!(status.unwrap().code(), Some(3));
+		// the child sends SIGTERM to the current process, which forwards it back
+		let mut command = Command::new("sh");
+		command.args([
+			"-c",
+			r#"trap "exit 7" TERM; kill -TERM $PPID; sleep 5 & wait"#,
+		]);
+		let status = exec_with_secrets(&mut client, &mapping, command, &FlattenRules::default());
+		assert_eq!(status.unwrap().code(), Some(7));
+		// SIGQUIT sent to the current process only (like `docker stop` with STOPSIGNAL SIGQUIT)
+		// is forwarded too, unless it came from the terminal to the whole foreground group
+		if !in_foreground() {
+			let mut command = Command::new("sh");
+			command.args([
+				"-c",
+				r#"trap "exit 8" QUIT; kill -QUIT $PPID; sleep 5 & wait"#,
+			]);
+			let status =
+				exec_with_secrets(&mut client, &mapping, command, &FlattenRules::default());
+			assert_eq!(status.unwrap().code(), Some(8));
+		}
+		let mut after: libc::sigaction = unsafe { mem::zeroed() };
+		sigterm(&mut after);
+		assert_eq!(after.sa_sigaction, before.sa_sigaction);
+	}
+
+	#[test]
+	fn parse_env_mapping() {
+		let (name, path) = parse_mapping("DB_PASS=vault:role:kv/db#/password").unwrap();
+		assert_eq!(name, "DB_PASS");
+		assert_eq!(path.anchor, Some("/password"));
+		assert!(parse_mapping("vault:role:kv/db").is_err());
+	}
+}
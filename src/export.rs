@@ -0,0 +1,206 @@
+use crate::{
+	client::VaultClient,
+	error::{Error, Result},
+	secret::{value_to_string, Backend, SecretPath},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde_json::{Map, Value};
+use std::{borrow::Borrow, fmt::Write};
+
+/// Output formats of exported secrets
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Format {
+	/// `NAME="value"` lines readable by docker-compose
+	Dotenv,
+	/// a json object
+	Json,
+	/// a yaml mapping
+	Yaml,
+	/// `export NAME='value'` lines to be sourced by a posix shell
+	Shell,
+	/// a kubernetes Secret manifest
+	Secret {
+		name: String,
+		namespace: Option<String>,
+	},
+}
+
+/// Export named secret values in the given format. Non string values are serialized to json in
+/// the formats that only accept strings
+pub fn export<V: Borrow<Value>>(values: &[(&str, V)], format: &Format) -> Result<String> {
+	let mut output = String::new();
+	match format {
+		Format::Dotenv => {
+			for (name, value) in values.iter() {
+				check_variable(name)?;
+				let _ = writeln!(
+					output,
+					"{}=\"{}\"",
+					name,
+					escape_dotenv(&value_to_string(value.borrow()))
+				);
+			}
+		}
+		Format::Shell => {
+			for (name, value) in values.iter() {
+				check_variable(name)?;
+				let _ = writeln!(
+					output,
+					"export {}={}",
+					name,
+					quote_shell(&value_to_string(value.borrow()))
+				);
+			}
+		}
+		Format::Json => {
+			let object: Map<String, Value> = values
+				.iter()
+				.map(|(name, value)| ((*name).to_owned(), value.borrow().clone()))
+				.collect();
+			output = serde_json::to_string_pretty(&object)
+				.map_err(|e| Error::ParseError { source: e })?;
+			output.push('\n');
+		}
+		Format::Yaml => {
+			// json is a subset of yaml: keys and values are written as json
+			for (name, value) in values.iter() {
+				let _ = writeln!(output, "{}: {}", Value::from(*name), value.borrow());
+			}
+		}
+		Format::Secret { name, namespace } => {
+			output.push_str("apiVersion: v1\nkind: Secret\nmetadata:\n");
+			let _ = writeln!(output, "  name: {}", Value::from(name.as_str()));
+			if let Some(namespace) = namespace {
+				let _ = writeln!(output, "  namespace: {}", Value::from(namespace.as_str()));
+			}
+			output.push_str("type: Opaque\ndata:\n");
+			for (name, value) in values.iter() {
+				check_key(name)?;
+				let _ = writeln!(
+					output,
+					"  {}: {}",
+					name,
+					STANDARD.encode(value_to_string(value.borrow()))
+				);
+			}
+		}
+	}
+	Ok(output)
+}
+
+/// Resolve named secret paths and export their values in the given format
+pub fn resolve_and_export(
+	client: &mut VaultClient,
+	paths: &[(&str, SecretPath<'_, Backend>)],
+	format: &Format,
+) -> Result<String> {
+	let values = paths
+		.iter()
+		.map(|(name, path)| client.resolve(path).map(|value| (*name, value)))
+		.collect::<Result<Vec<_>>>()?;
+	let exposed: Vec<_> = values
+		.iter()
+		.map(|(name, value)| (*name, value.expose()))
+		.collect();
+	export(&exposed, format)
+}
+
+/// Check that a name is a valid environment variable name
+fn check_variable(name: &str) -> Result<()> {
+	let mut chars = name.chars();
+	match chars.next() {
+		Some(c) if c.is_ascii_alphabetic() || c == '_' => (),
+		_ => return Err(Error::InvalidName(name.to_owned())),
+	}
+	if chars.all(|c| c.is_ascii_alphanumeric() || c == '_') {
+		Ok(())
+	} else {
+		Err(Error::InvalidName(name.to_owned()))
+	}
+}
+
+/// Check that a name is a valid kubernetes Secret key
+fn check_key(name: &str) -> Result<()> {
+	if !name.is_empty()
+		&& name
+			.chars()
+			.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+	{
+		Ok(())
+	} else {
+		Err(Error::InvalidName(name.to_owned()))
+	}
+}
+
+/// Escape a value to put it between double quotes in a dotenv file
+fn escape_dotenv(value: &str) -> String {
+	let mut escaped = String::with_capacity(value.len());
+	for c in value.chars() {
+		match c {
+			'\\' => escaped.push_str("\\\\"),
+			'"' => escaped.push_str("\\\""),
+			'$' => escaped.push_str("\\$"),
+			'\n' => escaped.push_str("\\n"),
+			'\r' => escaped.push_str("\\r"),
+			c => escaped.push(c),
+		}
+	}
+	escaped
+}
+
+/// Quote a value for a posix shell (nothing is interpreted between single quotes)
+fn quote_shell(value: &str) -> String {
+	format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use serde_json::json;
+
+	fn values() -> Vec<(&'static str, Value)> {
+		vec![
+			("USER", json!("ad'min")),
+			("PASS", json!("p\"a$s\\s\nword")),
+			("DB", json!({"port": 5432})),
+		]
+	}
+
+	#[test]
+	fn export_dotenv() {
+		assert_eq!(
+			export(&values(), &Format::Dotenv).unwrap(),
+			"USER=\"ad'min\"\nPASS=\"p\\\"a\\$s\\\\s\\nword\"\nDB=\"{\\\"port\\\":5432}\"\n"
+		);
+	}
+
+	#[test]
+	fn export_shell() {
+		assert_eq!(
+			export(&values()[..1], &Format::Shell).unwrap(),
+			"export USER='ad'\\''min'\n"
+		);
+		assert!(export(&[("1USER", json!("admin"))], &Format::Shell).is_err());
+	}
+
+	#[test]
+	fn export_yaml() {
+		assert_eq!(
+			export(&values(), &Format::Yaml).unwrap(),
+			"\"USER\": \"ad'min\"\n\"PASS\": \"p\\\"a$s\\\\s\\nword\"\n\"DB\": {\"port\":5432}\n"
+		);
+	}
+
+	#[test]
+	fn export_secret() {
+		let format = Format::Secret {
+			name: "app".to_owned(),
+			namespace: None,
+		};
+		assert_eq!(
+			export(&values()[..1], &format).unwrap(),
+			"apiVersion: v1\nkind: Secret\nmetadata:\n  name: \"app\"\ntype: Opaque\ndata:\n  USER: YWQnbWlu\n"
+		);
+	}
+}
@@ -0,0 +1,254 @@
+use crate::{
+	error::{Error, Result},
+	redact::Jwt,
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Number, Value};
+use std::{
+	env, fmt, fs, io,
+	path::PathBuf,
+	time::{SystemTime, UNIX_EPOCH},
+};
+use zeroize::Zeroize;
+
+/// Tolerated clock difference with the jwt issuer in seconds
+const LEEWAY: u64 = 60;
+
+/// Where to read the jwt from. The source is read again before each login so that rotated
+/// tokens (kubernetes projected service account tokens, CI job tokens) are taken into account
+pub enum JwtSource {
+	/// a jwt given once and for all
+	Static(Jwt),
+	/// a file containing the jwt
+	File(PathBuf),
+	/// an environment variable containing the jwt
+	Env(String),
+	/// a function returning the jwt
+	Fn(Box<dyn Fn() -> io::Result<String> + Send + Sync>),
+}
+
+impl JwtSource {
+	/// read the jwt from the source
+	pub fn read(&self) -> Result<Jwt> {
+		let mut jwt = match self {
+			JwtSource::Static(jwt) => return Ok(Jwt::new(jwt.expose().trim())),
+			JwtSource::File(path) => fs::read_to_string(path),
+			JwtSource::Env(var) => {
+				env::var(var).map_err(|e| io::Error::new(io::ErrorKind::NotFound, e))
+			}
+			JwtSource::Fn(f) => f(),
+		}
+		.map_err(|e| Error::JwtSourceError {
+			src: self.to_string(),
+			source: e,
+		})?;
+		let trimmed = Jwt::new(jwt.trim());
+		jwt.zeroize();
+		Ok(trimmed)
+	}
+}
+
+/// Describe the source without revealing the jwt
+impl fmt::Display for JwtSource {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			JwtSource::Static(_) => write!(f, "static jwt"),
+			JwtSource::File(path) => write!(f, "file {}", path.display()),
+			JwtSource::Env(var) => write!(f, "environment variable {}", var),
+			JwtSource::Fn(_) => write!(f, "jwt function"),
+		}
+	}
+}
+
+impl fmt::Debug for JwtSource {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "JwtSource({})", self)
+	}
+}
+
+/// Audience of a jwt: a string or a list of strings
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Audience {
+	One(String),
+	Many(Vec<String>),
+}
+
+impl Audience {
+	/// check if the audience contains aud
+	pub fn contains(&self, aud: &str) -> bool {
+		match self {
+			Audience::One(one) => one == aud,
+			Audience::Many(many) => many.iter().any(|a| a == aud),
+		}
+	}
+}
+
+/// Claims of a jwt, decoded locally without verifying the signature
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct Claims {
+	pub iss: Option<String>,
+	pub sub: Option<String>,
+	pub aud: Option<Audience>,
+	/// expiration time (seconds since epoch)
+	#[serde(default, deserialize_with = "numeric_date")]
+	pub exp: Option<u64>,
+	/// not before time (seconds since epoch)
+	#[serde(default, deserialize_with = "numeric_date")]
+	pub nbf: Option<u64>,
+	/// issued at time (seconds since epoch)
+	#[serde(default, deserialize_with = "numeric_date")]
+	pub iat: Option<u64>,
+	pub jti: Option<String>,
+	/// custom claims
+	#[serde(flatten)]
+	pub extra: Map<String, Value>,
+}
+
+/// Deserialize a NumericDate, which may have a fractional part, truncated to whole seconds
+fn numeric_date<'de, D: Deserializer<'de>>(
+	deserializer: D,
+) -> std::result::Result<Option<u64>, D::Error> {
+	Ok(Option::<Number>::deserialize(deserializer)?.map(|date| {
+		date.as_u64()
+			.unwrap_or_else(|| date.as_f64().unwrap_or(0.0).max(0.0) as u64)
+	}))
+}
+
+impl Claims {
+	/// decode the payload of a jwt without verifying its signature
+	pub fn decode(jwt: &str) -> Result<Self> {
+		let payload = jwt
+			.split('.')
+			.nth(1)
+			.ok_or_else(|| Error::InvalidJwt("missing payload".to_owned()))?;
+		let payload = URL_SAFE_NO_PAD
+			.decode(payload.trim_end_matches('='))
+			.map_err(|e| Error::InvalidJwt(e.to_string()))?;
+		serde_json::from_slice(&payload).map_err(|e| Error::InvalidJwt(e.to_string()))
+	}
+
+	/// return a custom claim
+	pub fn get(&self, name: &str) -> Option<&Value> {
+		self.extra.get(name)
+	}
+
+	/// check that the jwt is not expired and already valid at the given time (seconds since
+	/// epoch) with a small tolerance for clock skew
+	pub fn check_time(&self, now: u64) -> Result<()> {
+		match (self.exp, self.nbf) {
+			(Some(exp), _) if now >= exp.saturating_add(LEEWAY) => Err(Error::JwtExpired(exp)),
+			(_, Some(nbf)) if now.saturating_add(LEEWAY) < nbf => Err(Error::JwtNotYetValid(nbf)),
+			_ => Ok(()),
+		}
+	}
+
+	/// check that the jwt is not expired and already valid
+	pub fn check_now(&self) -> Result<()> {
+		let now = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|d| d.as_secs())
+			.unwrap_or(0);
+		self.check_time(now)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn read_sources() {
+		assert_eq!(
+			JwtSource::Static(Jwt::new("jwt\n"))
+				.read()
+				.unwrap()
+				.expose(),
+			"jwt"
+		);
+		assert_eq!(
+			JwtSource::Fn(Box::new(|| Ok("jwt".to_owned())))
+				.read()
+				.unwrap()
+				.expose(),
+			"jwt"
+		);
+	}
+
+	#[test]
+	fn error_names_the_source() {
+		let err = JwtSource::File(PathBuf::from("/nonexistent/token"))
+			.read()
+			.unwrap_err();
+		assert_eq!(
+			err.to_string(),
+			"unable to read the jwt from file /nonexistent/token"
+		);
+		let err = JwtSource::Env("VAULT_JWT_NONEXISTENT_VAR".to_owned())
+			.read()
+			.unwrap_err();
+		assert_eq!(
+			err.to_string(),
+			"unable to read the jwt from environment variable VAULT_JWT_NONEXISTENT_VAR"
+		);
+	}
+
+	/// build an unsigned jwt from claims
+	fn jwt(claims: &str) -> String {
+		format!(
+			"{}.{}.",
+			URL_SAFE_NO_PAD.encode(r#"{"alg":"none"}"#),
+			URL_SAFE_NO_PAD.encode(claims)
+		)
+	}
+
+	#[test]
+	fn decode_claims() {
+		let claims = Claims::decode(&jwt(
+			r#"{"iss":"gitlab","sub":"project_path:grp/app","aud":["vault"],"exp":2000,"project_id":"42"}"#,
+		))
+		.unwrap();
+		assert_eq!(claims.iss.as_deref(), Some("gitlab"));
+		assert!(claims.aud.as_ref().unwrap().contains("vault"));
+		assert_eq!(claims.get("project_id"), Some(&Value::from("42")));
+		assert!(claims.check_time(1000).is_ok());
+		assert!(matches!(
+			claims.check_time(3000),
+			Err(Error::JwtExpired(2000))
+		));
+		assert!(Claims::decode("not a jwt").is_err());
+	}
+
+	#[test]
+	fn not_yet_valid() {
+		let claims = Claims::decode(&jwt(r#"{"nbf":2000}"#)).unwrap();
+		assert!(matches!(
+			claims.check_time(1000),
+			Err(Error::JwtNotYetValid(2000))
+		));
+	}
+
+	#[test]
+	fn fractional_times() {
+		let claims = Claims::decode(&jwt(r#"{"exp":1.5e9,"nbf":1000.9,"iat":-1.0}"#)).unwrap();
+		assert_eq!(claims.exp, Some(1_500_000_000));
+		assert_eq!(claims.nbf, Some(1000));
+		assert_eq!(claims.iat, Some(0));
+		assert!(claims.check_time(1_500_000_000).is_ok());
+		assert!(Claims::decode(&jwt(r#"{"exp":"soon"}"#)).is_err());
+	}
+
+	#[test]
+	fn huge_times_do_not_overflow() {
+		let claims = Claims::decode(&jwt(&format!(r#"{{"exp":{}}}"#, u64::MAX))).unwrap();
+		assert!(claims.check_time(u64::MAX - 1).is_ok());
+		let claims = Claims::decode(&jwt(&format!(r#"{{"nbf":{}}}"#, u64::MAX))).unwrap();
+		assert!(claims.check_time(u64::MAX).is_ok());
+		assert!(matches!(
+			claims.check_time(0),
+			Err(Error::JwtNotYetValid(_))
+		));
+	}
+}
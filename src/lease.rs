@@ -1,33 +1,137 @@
-use std::time::{SystemTime, Duration};
+use std::time::{Duration, Instant};
 
-#[derive(Debug)]
+/// Define when a lease should be renewed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenewPolicy {
+	/// fraction of the lease duration after which the lease should be renewed
+	pub fraction: f64,
+	/// minimum time left before expiration when the lease should be renewed
+	pub min_grace: Duration,
+	/// maximum random duration substracted from the renew delay to spread renewals
+	pub jitter: Duration,
+}
+
+impl Default for RenewPolicy {
+	/// renew after 2/3 of the lease duration without jitter
+	fn default() -> Self {
+		Self {
+			fraction: 2.0 / 3.0,
+			min_grace: Duration::from_secs(0),
+			jitter: Duration::from_secs(0),
+		}
+	}
+}
+
+impl RenewPolicy {
+	/// return the delay after which a lease of the given duration should be renewed. A NaN
+	/// fraction is replaced by the default one
+	pub fn renew_delay(&self, dur: Duration) -> Duration {
+		let fraction = if self.fraction.is_nan() {
+			Self::default().fraction
+		} else {
+			self.fraction.clamp(0.0, 1.0)
+		};
+		let delay = dur
+			.mul_f64(fraction)
+			.min(dur.checked_sub(self.min_grace).unwrap_or_default());
+		let jitter = if self.jitter.is_zero() {
+			Duration::from_secs(0)
+		} else {
+			self.jitter.mul_f64(fastrand::f64())
+		};
+		delay.checked_sub(jitter).unwrap_or_default()
+	}
+}
+
+#[derive(Debug, Clone)]
 pub struct Lease {
 	/// start of the Lease
-	pub time: SystemTime,
+	pub start: Instant,
 	/// duration of the lease
 	pub lease_duration: Duration,
 	/// renew delay
-	pub renew_delay: Duration
+	pub renew_delay: Duration,
 }
 
 impl Lease {
+	/// create a lease starting now with the default renew policy
 	pub fn new(dur: Duration) -> Self {
+		Self::with_policy(dur, &RenewPolicy::default())
+	}
+
+	/// create a lease starting now with a given renew policy
+	pub fn with_policy(dur: Duration, policy: &RenewPolicy) -> Self {
 		Self {
-			time: SystemTime::now(),
+			start: Instant::now(),
 			lease_duration: dur,
-			renew_delay: dur * 2 / 3
+			renew_delay: policy.renew_delay(dur),
 		}
 	}
 
+	/// returns the expiration time of the lease
+	pub fn expires_at(&self) -> Instant {
+		self.start + self.lease_duration
+	}
+
+	/// returns the time left before expiration
+	pub fn remaining(&self) -> Duration {
+		self.expires_at().saturating_duration_since(Instant::now())
+	}
+
 	/// returns true if the lease is still valid
 	pub fn is_valid(&self) -> bool {
-		// TODO: replace with .is_zero() when stable
-		self.lease_duration != Duration::from_secs(0) && SystemTime::now() < self.time + self.lease_duration
+		!self.lease_duration.is_zero() && Instant::now() < self.expires_at()
 	}
 
 	/// returns true if the lease is about to expire
 	pub fn to_renew(&self) -> bool {
-		// TODO: replace with .is_zero() when stable
-		self.lease_duration != Duration::from_secs(0) && SystemTime::now() > self.time + self.renew_delay
+		!self.lease_duration.is_zero() && Instant::now() > self.start + self.renew_delay
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn default_policy_renews_after_two_thirds() {
+		let lease = Lease::new(Duration::from_secs(30));
+		assert_eq!(lease.renew_delay, Duration::from_secs(20));
+		assert!(lease.is_valid());
+		assert!(!lease.to_renew());
+		assert!(lease.remaining() <= Duration::from_secs(30));
+	}
+
+	#[test]
+	fn policy_keeps_grace_period_and_jitter() {
+		let policy = RenewPolicy {
+			fraction: 0.9,
+			min_grace: Duration::from_secs(60),
+			jitter: Duration::from_secs(10),
+		};
+		for _ in 0..100 {
+			let delay = policy.renew_delay(Duration::from_secs(300));
+			assert!(delay <= Duration::from_secs(240));
+			assert!(delay >= Duration::from_secs(230));
+		}
+		assert_eq!(
+			policy.renew_delay(Duration::from_secs(30)),
+			Duration::from_secs(0)
+		);
+	}
+
+	#[test]
+	fn non_finite_fraction() {
+		let policy = |fraction| RenewPolicy {
+			fraction,
+			..Default::default()
+		};
+		let dur = Duration::from_secs(30);
+		assert_eq!(policy(f64::NAN).renew_delay(dur), Duration::from_secs(20));
+		assert_eq!(policy(f64::INFINITY).renew_delay(dur), dur);
+		assert_eq!(
+			policy(f64::NEG_INFINITY).renew_delay(dur),
+			Duration::from_secs(0)
+		);
 	}
 }
@@ -1,8 +1,27 @@
+
 pub mod auth;
+pub mod cache;
 pub mod client;
 pub mod error;
+#[cfg(all(unix, feature = "exec"))]
+pub mod exec;
+pub mod export;
+pub mod jwt;
 pub mod lease;
+pub mod metrics;
+pub mod middleware;
+pub mod redact;
+#[cfg(any(test, feature = "test-util"))]
+pub mod mock;
 pub mod secret;
+#[cfg(all(unix, feature = "token-store"))]
+pub mod store;
+#[cfg(unix)]
+pub mod sink;
+pub mod sys;
+pub mod template;
+#[cfg(feature = "tracing")]
+pub mod trace;
 #[cfg(feature = "nom")]
 mod parser;
 #[cfg(not(feature = "nom"))]
@@ -0,0 +1,387 @@
+use crate::{cache::CacheStats, secret::Secret};
+
+use std::{
+	collections::HashMap,
+	sync::Mutex,
+	time::{Duration, Instant},
+};
+
+/// Upper bounds of the buckets of the latency histograms in seconds
+pub const LATENCY_BUCKETS: [f64; 10] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+/// Label of the status of requests that didn't get a response
+pub const NO_RESPONSE: &str = "error";
+
+/// Kind of the renewals of tokens
+pub const TOKEN: &str = "token";
+/// Kind of the renewals of secret leases
+pub const SECRET: &str = "secret";
+
+/// Histogram of durations with the buckets of LATENCY_BUCKETS
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+	/// cumulative number of observations less or equal than each bucket bound
+	pub buckets: Vec<u64>,
+	/// number of observations
+	pub count: u64,
+	/// sum of observations in seconds
+	pub sum: f64,
+}
+
+impl Default for Histogram {
+	fn default() -> Self {
+		Self {
+			buckets: vec![0; LATENCY_BUCKETS.len()],
+			count: 0,
+			sum: 0.0,
+		}
+	}
+}
+
+impl Histogram {
+	/// record a duration
+	pub fn observe(&mut self, dur: Duration) {
+		let secs = dur.as_secs_f64();
+		for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS.iter()) {
+			if secs <= *bound {
+				*bucket += 1;
+			}
+		}
+		self.count += 1;
+		self.sum += secs;
+	}
+}
+
+/// Metrics of a VaultClient at a given time
+#[derive(Debug, Clone, Default)]
+pub struct MetricsSnapshot {
+	/// number of requests by path and status code (or NO_RESPONSE)
+	pub requests: HashMap<(String, String), u64>,
+	/// latency of requests by path
+	pub latency: HashMap<String, Histogram>,
+	/// number of logins by role
+	pub logins: HashMap<String, u64>,
+	/// number of failed logins by role
+	pub login_failures: HashMap<String, u64>,
+	/// number of renewals by kind (TOKEN or SECRET)
+	pub renewals: HashMap<String, u64>,
+	/// number of failed renewals by kind (TOKEN or SECRET)
+	pub renewal_failures: HashMap<String, u64>,
+	/// statistics of the cache of secrets if enabled
+	pub cache: Option<CacheStats>,
+	/// time until the token of each role expires
+	pub token_expiry: HashMap<String, Duration>,
+	/// time until the first lease of each secret path expires. The path of a lease is its id
+	/// without the last segment, so that the labels don't change with every new lease
+	pub secret_expiry: HashMap<String, Duration>,
+}
+
+/// Metrics recorded by a VaultClient
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+	recorded: Mutex<Recorded>,
+}
+
+#[derive(Debug, Default)]
+struct Recorded {
+	requests: HashMap<(String, String), u64>,
+	latency: HashMap<String, Histogram>,
+	logins: HashMap<String, u64>,
+	login_failures: HashMap<String, u64>,
+	renewals: HashMap<String, u64>,
+	renewal_failures: HashMap<String, u64>,
+	/// expiration of the leased secrets by lease id (purged when expired)
+	secret_expiry: HashMap<String, Instant>,
+}
+
+impl Metrics {
+	/// record a request with the status of its response if any
+	pub fn request(&self, path: &str, status: Option<u16>, dur: Duration) {
+		let status = status
+			.map(|s| s.to_string())
+			.unwrap_or_else(|| NO_RESPONSE.to_owned());
+		let mut recorded = self.recorded.lock().unwrap();
+		*recorded
+			.requests
+			.entry((path.to_owned(), status))
+			.or_default() += 1;
+		recorded
+			.latency
+			.entry(path.to_owned())
+			.or_default()
+			.observe(dur);
+	}
+
+	/// record a login attempt
+	pub fn login(&self, role: &str, success: bool) {
+		let mut recorded = self.recorded.lock().unwrap();
+		*recorded.logins.entry(role.to_owned()).or_default() += 1;
+		if !success {
+			*recorded.login_failures.entry(role.to_owned()).or_default() += 1;
+		}
+	}
+
+	/// record a renewal attempt of the given kind
+	pub fn renewal(&self, kind: &str, success: bool) {
+		let mut recorded = self.recorded.lock().unwrap();
+		*recorded.renewals.entry(kind.to_owned()).or_default() += 1;
+		if !success {
+			*recorded
+				.renewal_failures
+				.entry(kind.to_owned())
+				.or_default() += 1;
+		}
+	}
+
+	/// track the expiration of a leased secret and forget the expired ones
+	pub fn secret(&self, secret: &Secret) {
+		let mut recorded = self.recorded.lock().unwrap();
+		let now = Instant::now();
+		recorded
+			.secret_expiry
+			.retain(|_, expires_at| *expires_at > now);
+		if let (Some(lease_id), Some(lease)) = (secret.lease_id(), secret.lease()) {
+			recorded
+				.secret_expiry
+				.insert(lease_id.to_owned(), lease.expires_at());
+		}
+	}
+
+	/// return the recorded metrics without the gauges of tokens and the cache statistics
+	pub fn snapshot(&self) -> MetricsSnapshot {
+		let recorded = self.recorded.lock().unwrap();
+		let now = Instant::now();
+		MetricsSnapshot {
+			requests: recorded.requests.clone(),
+			latency: recorded.latency.clone(),
+			logins: recorded.logins.clone(),
+			login_failures: recorded.login_failures.clone(),
+			renewals: recorded.renewals.clone(),
+			renewal_failures: recorded.renewal_failures.clone(),
+			secret_expiry: recorded
+				.secret_expiry
+				.iter()
+				.filter(|(_, expires_at)| **expires_at > now)
+				.fold(HashMap::new(), |mut expiry, (lease_id, expires_at)| {
+					let remaining = *expires_at - now;
+					expiry
+						.entry(lease_path(lease_id).to_owned())
+						.and_modify(|r: &mut Duration| *r = (*r).min(remaining))
+						.or_insert(remaining);
+					expiry
+				}),
+			..Default::default()
+		}
+	}
+}
+
+/// Return the path of a lease, its id without the random last segment
+fn lease_path(lease_id: &str) -> &str {
+	lease_id.rsplit_once('/').map_or(lease_id, |(path, _)| path)
+}
+
+#[cfg(feature = "prometheus")]
+impl MetricsSnapshot {
+	/// Render the metrics in the prometheus text exposition format
+	pub fn to_prometheus(&self) -> String {
+		use std::fmt::Write;
+
+		let mut out = String::new();
+		counter(
+			&mut out,
+			"vault_jwt_requests_total",
+			"Requests sent to vault",
+			self.requests.iter().map(|((path, status), v)| {
+				(
+					format!("path=\"{}\",status=\"{}\"", escape(path), status),
+					*v,
+				)
+			}),
+		);
+		let _ = writeln!(
+			out,
+			"# HELP vault_jwt_request_duration_seconds Latency of vault requests\n# TYPE vault_jwt_request_duration_seconds histogram"
+		);
+		let mut latency: Vec<_> = self.latency.iter().collect();
+		latency.sort_by(|a, b| a.0.cmp(b.0));
+		for (path, histogram) in latency {
+			let path = escape(path);
+			for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets.iter()) {
+				let _ = writeln!(
+					out,
+					"vault_jwt_request_duration_seconds_bucket{{path=\"{}\",le=\"{}\"}} {}",
+					path, bound, count
+				);
+			}
+			let _ = writeln!(
+				out,
+				"vault_jwt_request_duration_seconds_bucket{{path=\"{0}\",le=\"+Inf\"}} {1}\nvault_jwt_request_duration_seconds_sum{{path=\"{0}\"}} {2}\nvault_jwt_request_duration_seconds_count{{path=\"{0}\"}} {1}",
+				path, histogram.count, histogram.sum
+			);
+		}
+		counter(
+			&mut out,
+			"vault_jwt_logins_total",
+			"Login attempts",
+			labeled("role", &self.logins),
+		);
+		counter(
+			&mut out,
+			"vault_jwt_login_failures_total",
+			"Failed login attempts",
+			labeled("role", &self.login_failures),
+		);
+		counter(
+			&mut out,
+			"vault_jwt_renewals_total",
+			"Renewal attempts of tokens and secrets",
+			labeled("kind", &self.renewals),
+		);
+		counter(
+			&mut out,
+			"vault_jwt_renewal_failures_total",
+			"Failed renewals of tokens and secrets",
+			labeled("kind", &self.renewal_failures),
+		);
+		if let Some(cache) = self.cache {
+			counter(
+				&mut out,
+				"vault_jwt_cache_hits_total",
+				"Secrets served from the cache",
+				std::iter::once((String::new(), cache.hits)),
+			);
+			counter(
+				&mut out,
+				"vault_jwt_cache_misses_total",
+				"Secrets not found in the cache",
+				std::iter::once((String::new(), cache.misses)),
+			);
+		}
+		gauge(
+			&mut out,
+			"vault_jwt_token_expiry_seconds",
+			"Time until the token of a role expires",
+			"role",
+			&self.token_expiry,
+		);
+		gauge(
+			&mut out,
+			"vault_jwt_secret_expiry_seconds",
+			"Time until the first lease of a secret path expires",
+			"path",
+			&self.secret_expiry,
+		);
+		out
+	}
+}
+
+/// Escape a label value
+#[cfg(feature = "prometheus")]
+fn escape(value: &str) -> String {
+	value
+		.replace('\\', "\\\\")
+		.replace('"', "\\\"")
+		.replace('\n', "\\n")
+}
+
+/// Return the labels and values of a map keyed by a single label
+#[cfg(feature = "prometheus")]
+fn labeled<'a>(
+	label: &'a str,
+	map: &'a HashMap<String, u64>,
+) -> impl Iterator<Item = (String, u64)> + 'a {
+	map.iter()
+		.map(move |(k, v)| (format!("{}=\"{}\"", label, escape(k)), *v))
+}
+
+/// Write a counter with its samples sorted by labels
+#[cfg(feature = "prometheus")]
+fn counter<I>(out: &mut String, name: &str, help: &str, samples: I)
+where
+	I: Iterator<Item = (String, u64)>,
+{
+	use std::fmt::Write;
+
+	let mut samples: Vec<_> = samples.collect();
+	samples.sort();
+	let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter", name, help, name);
+	for (labels, value) in samples {
+		if labels.is_empty() {
+			let _ = writeln!(out, "{} {}", name, value);
+		} else {
+			let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+		}
+	}
+}
+
+/// Write a gauge of durations in seconds with its samples sorted by labels
+#[cfg(feature = "prometheus")]
+fn gauge(out: &mut String, name: &str, help: &str, label: &str, map: &HashMap<String, Duration>) {
+	use std::fmt::Write;
+
+	let mut samples: Vec<_> = map.iter().collect();
+	samples.sort();
+	let _ = writeln!(out, "# HELP {} {}\n# TYPE {} gauge", name, help, name);
+	for (key, value) in samples {
+		let _ = writeln!(
+			out,
+			"{}{{{}=\"{}\"}} {}",
+			name,
+			label,
+			escape(key),
+			value.as_secs()
+		);
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use serde_json::Value;
+
+	#[test]
+	fn histogram_buckets_are_cumulative() {
+		let mut histogram = Histogram::default();
+		histogram.observe(Duration::from_millis(20));
+		histogram.observe(Duration::from_secs(10));
+		assert_eq!(histogram.buckets, vec![0, 0, 1, 1, 1, 1, 1, 1, 1, 1]);
+		assert_eq!(histogram.count, 2);
+	}
+
+	#[test]
+	fn record_and_snapshot() {
+		let metrics = Metrics::default();
+		metrics.request("auth/jwt/login", Some(200), Duration::from_millis(1));
+		metrics.request("auth/jwt/login", None, Duration::from_millis(1));
+		metrics.login("app", false);
+		metrics.secret(
+			&Secret::new(Value::Null, Some(Duration::from_secs(60))).with_lease_id("db/creds/1"),
+		);
+		metrics.secret(
+			&Secret::new(Value::Null, Some(Duration::from_secs(120))).with_lease_id("db/creds/2"),
+		);
+		let snapshot = metrics.snapshot();
+		assert_eq!(
+			snapshot.requests[&("auth/jwt/login".to_owned(), NO_RESPONSE.to_owned())],
+			1
+		);
+		assert_eq!(snapshot.latency["auth/jwt/login"].count, 2);
+		assert_eq!(snapshot.login_failures["app"], 1);
+		assert_eq!(snapshot.secret_expiry.len(), 1);
+		assert!(snapshot.secret_expiry["db/creds"] <= Duration::from_secs(60));
+	}
+
+	#[cfg(feature = "prometheus")]
+	#[test]
+	fn prometheus_exposition() {
+		let metrics = Metrics::default();
+		metrics.login("app", true);
+		metrics.renewal(TOKEN, true);
+		let text = metrics.snapshot().to_prometheus();
+		assert!(text.contains(
+			"# TYPE vault_jwt_logins_total counter\nvault_jwt_logins_total{role=\"app\"} 1\n"
+		));
+		assert!(text.contains("vault_jwt_renewals_total{kind=\"token\"} 1\n"));
+		assert!(!text.contains("cache"));
+	}
+}
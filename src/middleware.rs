@@ -0,0 +1,103 @@
+use crate::error::Result;
+
+use isahc::http::{HeaderMap, Method, Request, StatusCode, Uri};
+use std::{fmt, sync::Arc, time::Duration};
+
+/// A request sent by a VaultClient and its outcome
+#[derive(Debug)]
+pub struct Exchange<'a> {
+	pub method: &'a Method,
+	/// address the request was built for (after the `before` hooks)
+	pub uri: &'a Uri,
+	/// status of the response or None if no response was received
+	pub status: Option<StatusCode>,
+	/// headers of the response if any
+	pub headers: Option<&'a HeaderMap>,
+	pub latency: Duration,
+}
+
+/// Hooks run around every request sent by a VaultClient (logins, secrets, token and sys calls),
+/// in sync and async mode alike
+pub trait Middleware: Send + Sync {
+	/// called before sending a request to add headers or rewrite its address. An error (like
+	/// `Error::Middleware`) aborts the request
+	fn before(&self, _request: &mut Request<String>) -> Result<()> {
+		Ok(())
+	}
+
+	/// called after receiving a response (or failing to)
+	fn after(&self, _exchange: &Exchange<'_>) {}
+}
+
+/// Ordered list of middlewares: `before` hooks run in insertion order and `after` hooks in
+/// reverse order
+#[derive(Default, Clone)]
+pub(crate) struct Middlewares(Vec<Arc<dyn Middleware>>);
+
+impl Middlewares {
+	pub fn push(&mut self, middleware: Arc<dyn Middleware>) {
+		self.0.push(middleware);
+	}
+
+	pub fn before(&self, request: &mut Request<String>) -> Result<()> {
+		self.0.iter().try_for_each(|m| m.before(request))
+	}
+
+	pub fn after(&self, exchange: &Exchange<'_>) {
+		self.0.iter().rev().for_each(|m| m.after(exchange));
+	}
+}
+
+impl fmt::Debug for Middlewares {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "Middlewares({})", self.0.len())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use isahc::http::header::HeaderValue;
+	use std::sync::Mutex;
+
+	/// add a header and log the order of the hooks
+	struct Tag(&'static str, Arc<Mutex<Vec<String>>>);
+
+	impl Middleware for Tag {
+		fn before(&self, request: &mut Request<String>) -> Result<()> {
+			request
+				.headers_mut()
+				.append("X-Tag", HeaderValue::from_static(self.0));
+			self.1.lock().unwrap().push(format!("before {}", self.0));
+			Ok(())
+		}
+
+		fn after(&self, _exchange: &Exchange<'_>) {
+			self.1.lock().unwrap().push(format!("after {}", self.0));
+		}
+	}
+
+	#[test]
+	fn hooks_order() {
+		let log = Arc::new(Mutex::new(Vec::new()));
+		let mut middlewares = Middlewares::default();
+		middlewares.push(Arc::new(Tag("a", log.clone())));
+		middlewares.push(Arc::new(Tag("b", log.clone())));
+		let mut request = Request::get("http://vault/v1/sys/health")
+			.body(String::new())
+			.unwrap();
+		middlewares.before(&mut request).unwrap();
+		middlewares.after(&Exchange {
+			method: request.method(),
+			uri: request.uri(),
+			status: None,
+			headers: None,
+			latency: Duration::from_millis(1),
+		});
+		assert_eq!(request.headers().get_all("X-Tag").iter().count(), 2);
+		assert_eq!(
+			*log.lock().unwrap(),
+			vec!["before a", "before b", "after b", "after a"]
+		);
+	}
+}
@@ -0,0 +1,609 @@
+//! In-process mock of a vault server to test clients offline (feature `test-util`).
+//!
+//! It implements jwt login, token lookup/renew/revoke/create, kv v1 and v2 secrets, leased
+//! secrets, `sys/mounts`, `sys/capabilities-self`, `sys/leases/renew` and the health endpoints.
+//! TTLs are controllable and errors can be injected on any path.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde_json::{json, Map, Value};
+use std::{
+	collections::{HashMap, VecDeque},
+	sync::{Arc, Mutex},
+	thread::{self, JoinHandle},
+	time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tiny_http::{Header, Response, Server};
+
+/// A token delivered by the mock server
+#[derive(Debug)]
+struct Token {
+	role: String,
+	accessor: String,
+	policies: Vec<String>,
+	meta: Map<String, Value>,
+	ttl: Duration,
+	issued: Instant,
+	renewable: bool,
+	batch: bool,
+	orphan: bool,
+}
+
+impl Token {
+	fn is_valid(&self) -> bool {
+		self.ttl.is_zero() || self.issued.elapsed() < self.ttl
+	}
+
+	fn remaining(&self) -> u64 {
+		self.ttl.saturating_sub(self.issued.elapsed()).as_secs()
+	}
+
+	fn auth_block(&self, client_token: &str) -> Value {
+		json!({
+			"client_token": client_token,
+			"accessor": self.accessor,
+			"policies": self.policies,
+			"token_policies": self.policies,
+			"metadata": self.meta,
+			"lease_duration": self.remaining(),
+			"renewable": self.renewable,
+			"entity_id": format!("entity-{}", self.role),
+			"token_type": if self.batch { "batch" } else { "service" },
+			"orphan": self.orphan,
+		})
+	}
+}
+
+/// A secret stored in the mock server
+#[derive(Debug)]
+struct StoredSecret {
+	data: Value,
+	ttl: Option<Duration>,
+}
+
+/// State of the mock server
+#[derive(Debug)]
+struct State {
+	jwt: Option<String>,
+	roles: HashMap<String, Vec<String>>,
+	token_ttl: Duration,
+	token_renewable: bool,
+	batch_tokens: bool,
+	tokens: HashMap<String, Token>,
+	secrets: HashMap<String, StoredSecret>,
+	mounts: HashMap<String, (String, Option<String>)>,
+	leases: HashMap<String, Duration>,
+	capabilities: HashMap<String, Vec<String>>,
+	faults: HashMap<String, VecDeque<(u16, String)>>,
+	sealed: bool,
+	standby: bool,
+	requests: Vec<String>,
+	/// headers of the last request
+	headers: Vec<(String, String)>,
+	/// token injected in requests without token
+	agent_token: Option<String>,
+	counter: u64,
+}
+
+impl State {
+	fn new() -> Self {
+		let mut mounts = HashMap::new();
+		mounts.insert("sys/".to_owned(), ("system".to_owned(), None));
+		Self {
+			jwt: None,
+			roles: HashMap::new(),
+			token_ttl: Duration::from_secs(3600),
+			token_renewable: true,
+			batch_tokens: false,
+			tokens: HashMap::new(),
+			secrets: HashMap::new(),
+			mounts,
+			leases: HashMap::new(),
+			capabilities: HashMap::new(),
+			faults: HashMap::new(),
+			sealed: false,
+			standby: false,
+			requests: Vec::new(),
+			headers: Vec::new(),
+			agent_token: None,
+			counter: 0,
+		}
+	}
+
+	fn next_id(&mut self) -> u64 {
+		self.counter += 1;
+		self.counter
+	}
+
+	/// deliver a new token and return its auth block
+	fn new_token(
+		&mut self,
+		role: &str,
+		policies: Vec<String>,
+		ttl: Duration,
+		renewable: bool,
+		orphan: bool,
+		meta: Map<String, Value>,
+	) -> Value {
+		let id = self.next_id();
+		let prefix = if self.batch_tokens { "hvb" } else { "hvs" };
+		let client_token = format!("{}.mock{}", prefix, id);
+		let token = Token {
+			role: role.to_owned(),
+			accessor: format!("accessor{}", id),
+			policies,
+			meta,
+			ttl,
+			issued: Instant::now(),
+			renewable: renewable && !self.batch_tokens,
+			batch: self.batch_tokens,
+			orphan,
+		};
+		let auth = token.auth_block(&client_token);
+		self.tokens.insert(client_token, token);
+		json!({ "auth": auth })
+	}
+
+	/// handle a request and return the status and the json body of the response
+	fn handle(
+		&mut self,
+		method: &str,
+		path: &str,
+		token: Option<&str>,
+		body: Value,
+	) -> (u16, Value) {
+		self.requests.push(format!("{} {}", method, path));
+		if let Some((status, error)) = self.faults.get_mut(path).and_then(|f| f.pop_front()) {
+			return (status, errors(&[&error]));
+		}
+		match (method, path) {
+			("GET", "sys/health") => (200, self.health()),
+			("GET", "sys/seal-status") => (
+				200,
+				json!({"type": "shamir", "initialized": true, "sealed": self.sealed, "t": 1, "n": 1,
+					"progress": 0, "nonce": "", "version": "1.9.0", "migration": false,
+					"recovery_seal": false, "storage_type": "inmem"}),
+			),
+			("GET", "sys/leader") => (
+				200,
+				json!({"ha_enabled": false, "is_self": !self.standby, "leader_address": "",
+					"leader_cluster_address": "", "performance_standby": false,
+					"performance_standby_last_remote_wal": 0}),
+			),
+			(_, _) if self.sealed => (503, errors(&["Vault is sealed"])),
+			("POST", path) if path.starts_with("auth/") && path.ends_with("/login") => {
+				self.login(body)
+			}
+			_ => match token
+				.map(str::to_owned)
+				.or_else(|| self.agent_token.clone())
+			{
+				Some(token) if self.tokens.get(&token).filter(|t| t.is_valid()).is_some() => {
+					self.handle_authenticated(method, path, &token, body)
+				}
+				_ => (403, errors(&["permission denied"])),
+			},
+		}
+	}
+
+	fn health(&self) -> Value {
+		json!({"initialized": true, "sealed": self.sealed, "standby": self.standby,
+			"performance_standby": false, "replication_performance_mode": "disabled",
+			"replication_dr_mode": "disabled", "server_time_utc": now(), "version": "1.9.0",
+			"cluster_name": "mock", "cluster_id": "mock"})
+	}
+
+	fn login(&mut self, body: Value) -> (u16, Value) {
+		let role = body["role"].as_str().unwrap_or("");
+		let jwt = body["jwt"].as_str().unwrap_or("");
+		if jwt.is_empty()
+			|| self
+				.jwt
+				.as_ref()
+				.filter(|expected| *expected != jwt)
+				.is_some()
+		{
+			return (400, errors(&["error validating token: invalid signature"]));
+		}
+		match self.roles.get(role).cloned() {
+			Some(policies) => {
+				let (ttl, renewable) = (self.token_ttl, self.token_renewable);
+				(
+					200,
+					self.new_token(role, policies, ttl, renewable, true, Map::new()),
+				)
+			}
+			None => (
+				400,
+				errors(&[&format!("role \"{}\" could not be found", role)]),
+			),
+		}
+	}
+
+	fn handle_authenticated(
+		&mut self,
+		method: &str,
+		path: &str,
+		token: &str,
+		body: Value,
+	) -> (u16, Value) {
+		let write = method == "POST" || method == "PUT";
+		match (method, path) {
+			("GET", "auth/token/lookup-self") => {
+				let t = &self.tokens[token];
+				(
+					200,
+					json!({"data": {
+						"accessor": t.accessor, "display_name": format!("jwt-{}", t.role),
+						"entity_id": format!("entity-{}", t.role), "meta": t.meta, "orphan": t.orphan,
+						"path": "auth/jwt/login", "policies": t.policies, "renewable": t.renewable,
+						"ttl": t.remaining(), "creation_ttl": t.ttl.as_secs(),
+						"type": if t.batch { "batch" } else { "service" },
+					}}),
+				)
+			}
+			(_, "auth/token/renew-self") if write => {
+				let t = self.tokens.get_mut(token).unwrap();
+				if !t.renewable {
+					return (400, errors(&["lease is not renewable"]));
+				}
+				t.issued = Instant::now();
+				(200, json!({ "auth": t.auth_block(token) }))
+			}
+			(_, "auth/token/revoke-self") if write => {
+				if self.tokens[token].batch {
+					return (400, errors(&["batch tokens cannot be revoked"]));
+				}
+				self.tokens.remove(token);
+				(204, Value::Null)
+			}
+			(_, "auth/token/create") | (_, "auth/token/create-orphan") if write => {
+				let parent = &self.tokens[token];
+				let role = parent.role.clone();
+				let policies = body["policies"]
+					.as_array()
+					.filter(|p| !p.is_empty())
+					.map(|p| {
+						p.iter()
+							.filter_map(|p| p.as_str().map(str::to_owned))
+							.collect()
+					})
+					.unwrap_or_else(|| parent.policies.clone());
+				let ttl = body["ttl"]
+					.as_str()
+					.and_then(|ttl| ttl.trim_end_matches('s').parse().ok())
+					.map(Duration::from_secs)
+					.unwrap_or(parent.ttl);
+				let renewable = body["renewable"].as_bool().unwrap_or(true);
+				let meta = body["meta"].as_object().cloned().unwrap_or_default();
+				let orphan = path.ends_with("orphan");
+				(
+					200,
+					self.new_token(&role, policies, ttl, renewable, orphan, meta),
+				)
+			}
+			(_, "sys/capabilities-self") if write => {
+				let mut data = Map::new();
+				for path in body["paths"].as_array().cloned().unwrap_or_default().iter() {
+					let path = path.as_str().unwrap_or("");
+					data.insert(path.to_owned(), json!(self.path_capabilities(path)));
+				}
+				let mut res = data.clone();
+				res.insert("data".to_owned(), Value::Object(data));
+				(200, Value::Object(res))
+			}
+			("GET", "sys/mounts") => {
+				let mut data = Map::new();
+				for (mount, (mount_type, version)) in self.mounts.iter() {
+					let options = version.as_ref().map(|v| json!({ "version": v }));
+					data.insert(
+						mount.to_owned(),
+						json!({"type": mount_type, "options": options}),
+					);
+				}
+				(200, json!({ "data": data }))
+			}
+			(_, "sys/leases/renew") if write => {
+				let lease_id = body["lease_id"].as_str().unwrap_or("");
+				match self.leases.get(lease_id) {
+					Some(ttl) => (
+						200,
+						json!({"lease_id": lease_id, "renewable": true, "lease_duration": ttl.as_secs()}),
+					),
+					None => (400, errors(&["lease not found"])),
+				}
+			}
+			(_, path) => match self.secrets.get(path) {
+				Some(secret) => {
+					let (data, ttl) = (secret.data.clone(), secret.ttl);
+					let lease_id = match ttl {
+						Some(ttl) => {
+							let lease_id = format!("{}/{}", path, self.next_id());
+							self.leases.insert(lease_id.clone(), ttl);
+							lease_id
+						}
+						None => String::new(),
+					};
+					(
+						200,
+						json!({"request_id": format!("req{}", self.next_id()), "lease_id": lease_id,
+							"renewable": ttl.is_some(), "lease_duration": ttl.map(|t| t.as_secs()).unwrap_or(0),
+							"data": data}),
+					)
+				}
+				None => (404, errors(&[])),
+			},
+		}
+	}
+
+	fn path_capabilities(&self, path: &str) -> Vec<String> {
+		self.capabilities.get(path).cloned().unwrap_or_else(|| {
+			if self.secrets.contains_key(path) {
+				vec!["read".to_owned(), "update".to_owned()]
+			} else {
+				vec!["deny".to_owned()]
+			}
+		})
+	}
+}
+
+/// Body of an error response
+fn errors(errors: &[&str]) -> Value {
+	json!({ "errors": errors })
+}
+
+/// Seconds since epoch
+fn now() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_secs())
+		.unwrap_or(0)
+}
+
+/// An in-process mock vault server listening on a random local port. The server is stopped
+/// when dropped
+pub struct MockVault {
+	url: String,
+	state: Arc<Mutex<State>>,
+	server: Arc<Server>,
+	thread: Option<JoinHandle<()>>,
+}
+
+impl MockVault {
+	/// start a mock server accepting any jwt
+	pub fn start() -> Self {
+		let server = Server::http("127.0.0.1:0").expect("unable to start mock vault");
+		let port = server
+			.server_addr()
+			.to_ip()
+			.map(|addr| addr.port())
+			.expect("mock vault not listening on ip");
+		Self::serve(server, format!("http://127.0.0.1:{}/v1", port))
+	}
+
+	/// start a mock server listening on a unix socket (like a vault agent) accepting any jwt
+	#[cfg(unix)]
+	pub fn start_unix<P: AsRef<std::path::Path>>(socket: P) -> Self {
+		let server = Server::http_unix(socket.as_ref()).expect("unable to start mock vault");
+		Self::serve(server, format!("unix://{}", socket.as_ref().display()))
+	}
+
+	fn serve(server: Server, url: String) -> Self {
+		let server = Arc::new(server);
+		let state = Arc::new(Mutex::new(State::new()));
+		let thread = {
+			let (server, state) = (server.clone(), state.clone());
+			thread::spawn(move || serve(&server, &state))
+		};
+		Self {
+			url,
+			state,
+			server,
+			thread: Some(thread),
+		}
+	}
+
+	/// authenticate the requests without token with a token of role, like a vault agent with
+	/// auto-auth
+	pub fn enable_agent(&self, role: &str) {
+		let mut state = self.state.lock().unwrap();
+		let policies = state.roles.get(role).cloned().unwrap_or_default();
+		let ttl = state.token_ttl;
+		let auth = state.new_token(role, policies, ttl, true, true, Map::new());
+		state.agent_token = auth["auth"]["client_token"].as_str().map(str::to_owned);
+	}
+
+	/// address of the api to give to a VaultClient
+	pub fn url(&self) -> &str {
+		&self.url
+	}
+
+	/// return an unsigned jwt expiring in one hour
+	pub fn jwt(&self) -> String {
+		let claims = json!({"iss": "mock", "sub": "test", "aud": "vault", "iat": now(), "exp": now() + 3600});
+		format!(
+			"{}.{}.",
+			URL_SAFE_NO_PAD.encode(r#"{"alg":"none","typ":"JWT"}"#),
+			URL_SAFE_NO_PAD.encode(claims.to_string())
+		)
+	}
+
+	/// only accept the given jwt for login
+	pub fn set_jwt(&self, jwt: &str) {
+		self.state.lock().unwrap().jwt = Some(jwt.to_owned());
+	}
+
+	/// declare a role giving the policies to the tokens obtained at login
+	pub fn add_role(&self, role: &str, policies: &[&str]) {
+		self.state.lock().unwrap().roles.insert(
+			role.to_owned(),
+			policies.iter().map(|p| (*p).to_owned()).collect(),
+		);
+	}
+
+	/// set the ttl and renewability of the tokens delivered at login
+	pub fn set_token_ttl(&self, ttl: Duration, renewable: bool) {
+		let mut state = self.state.lock().unwrap();
+		state.token_ttl = ttl;
+		state.token_renewable = renewable;
+	}
+
+	/// deliver batch tokens instead of service tokens
+	pub fn set_batch_tokens(&self, batch: bool) {
+		self.state.lock().unwrap().batch_tokens = batch;
+	}
+
+	/// store a secret in a kv version 1 mount
+	pub fn add_kv1(&self, mount: &str, path: &str, data: Value) {
+		let mut state = self.state.lock().unwrap();
+		state.mounts.insert(
+			format!("{}/", mount),
+			("kv".to_owned(), Some("1".to_owned())),
+		);
+		state.secrets.insert(
+			format!("{}/{}", mount, path),
+			StoredSecret { data, ttl: None },
+		);
+	}
+
+	/// store a secret in a kv version 2 mount
+	pub fn add_kv2(&self, mount: &str, path: &str, data: Value) {
+		let mut state = self.state.lock().unwrap();
+		state.mounts.insert(
+			format!("{}/", mount),
+			("kv".to_owned(), Some("2".to_owned())),
+		);
+		let data = json!({"data": data, "metadata": {"version": 1, "destroyed": false}});
+		state.secrets.insert(
+			format!("{}/data/{}", mount, path),
+			StoredSecret { data, ttl: None },
+		);
+	}
+
+	/// store a leased secret answered to any method (like `database/creds/app` or `pki/issue/app`)
+	pub fn add_leased_secret(&self, path: &str, data: Value, ttl: Duration) {
+		self.state.lock().unwrap().secrets.insert(
+			path.to_owned(),
+			StoredSecret {
+				data,
+				ttl: Some(ttl),
+			},
+		);
+	}
+
+	/// set the capabilities returned for a path by `sys/capabilities-self`
+	pub fn set_capabilities(&self, path: &str, capabilities: &[&str]) {
+		self.state.lock().unwrap().capabilities.insert(
+			path.to_owned(),
+			capabilities.iter().map(|c| (*c).to_owned()).collect(),
+		);
+	}
+
+	/// answer the next request on path with an error
+	pub fn fail_next(&self, path: &str, status: u16, error: &str) {
+		self.state
+			.lock()
+			.unwrap()
+			.faults
+			.entry(path.to_owned())
+			.or_default()
+			.push_back((status, error.to_owned()));
+	}
+
+	/// seal or unseal the server
+	pub fn set_sealed(&self, sealed: bool) {
+		self.state.lock().unwrap().sealed = sealed;
+	}
+
+	/// make the server a standby node
+	pub fn set_standby(&self, standby: bool) {
+		self.state.lock().unwrap().standby = standby;
+	}
+
+	/// check if a token exists and is not expired
+	pub fn is_token_valid(&self, token: &str) -> bool {
+		self.state
+			.lock()
+			.unwrap()
+			.tokens
+			.get(token)
+			.filter(|t| t.is_valid())
+			.is_some()
+	}
+
+	/// revoke a token
+	pub fn revoke_token(&self, token: &str) {
+		self.state.lock().unwrap().tokens.remove(token);
+	}
+
+	/// number of tokens delivered and not revoked
+	pub fn token_count(&self) -> usize {
+		self.state.lock().unwrap().tokens.len()
+	}
+
+	/// requests received so far as `METHOD path`
+	pub fn requests(&self) -> Vec<String> {
+		self.state.lock().unwrap().requests.clone()
+	}
+
+	/// return the value of a header of the last request received
+	pub fn last_header(&self, name: &str) -> Option<String> {
+		self.state
+			.lock()
+			.unwrap()
+			.headers
+			.iter()
+			.find(|(field, _)| field.eq_ignore_ascii_case(name))
+			.map(|(_, value)| value.to_owned())
+	}
+}
+
+impl Drop for MockVault {
+	fn drop(&mut self) {
+		self.server.unblock();
+		if let Some(thread) = self.thread.take() {
+			let _ = thread.join();
+		}
+	}
+}
+
+/// Answer requests until the server is unblocked
+fn serve(server: &Server, state: &Mutex<State>) {
+	for mut request in server.incoming_requests() {
+		let method = request.method().as_str().to_owned();
+		let url = request.url().to_owned();
+		let path = url
+			.split('?')
+			.next()
+			.unwrap_or("")
+			.trim_start_matches("/v1/")
+			.to_owned();
+		let token = request
+			.headers()
+			.iter()
+			.find(|h| h.field.equiv("X-Vault-Token"))
+			.map(|h| h.value.as_str().to_owned());
+		let mut body = String::new();
+		let _ = request.as_reader().read_to_string(&mut body);
+		let body = serde_json::from_str(&body).unwrap_or(Value::Null);
+		let headers = request
+			.headers()
+			.iter()
+			.map(|h| (h.field.to_string(), h.value.to_string()))
+			.collect();
+		let (status, value) = {
+			let mut state = state.lock().unwrap();
+			state.headers = headers;
+			state.handle(&method, &path, token.as_deref(), body)
+		};
+		let _ = if status == 204 {
+			request.respond(Response::empty(204))
+		} else {
+			let header = Header::from_bytes("Content-Type", "application/json").unwrap();
+			request.respond(
+				Response::from_string(value.to_string())
+					.with_status_code(status)
+					.with_header(header),
+			)
+		};
+	}
+}
@@ -0,0 +1,314 @@
+//! Types holding credentials and secrets which are redacted when formatted with `{}` or `{:?}`.
+//! The plaintext is only available through the explicit `expose` accessors.
+//!
+//! The buffers are zeroed when dropped, so replaced tokens and evicted secrets are wiped too.
+//! With the `mlock` feature, they are also locked in memory to keep them out of swap (best
+//! effort: locking fails silently above `RLIMIT_MEMLOCK`). Locks are counted per page, so a
+//! page shared by several buffers stays locked until all of them are dropped.
+//!
+//! The copies handed to the http client to send a request (the login body and the
+//! `X-Vault-Token` header) are owned by it and are not wiped.
+
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+#[cfg(all(unix, feature = "mlock"))]
+use std::collections::BTreeMap;
+use std::fmt;
+use zeroize::Zeroize;
+
+/// Placeholder printed instead of a sensitive value
+pub const REDACTED: &str = "<redacted>";
+
+macro_rules! redacted_string {
+	($(#[$doc:meta])* $name:ident) => {
+		$(#[$doc])*
+		#[derive(Default, PartialEq, Eq)]
+		pub struct $name(String);
+
+		impl $name {
+			pub fn new<S: Into<String>>(value: S) -> Self {
+				let value = value.into();
+				lock(value.as_ptr(), value.len());
+				Self(value)
+			}
+
+			/// return the plaintext
+			pub fn expose(&self) -> &str {
+				&self.0
+			}
+
+			pub fn is_empty(&self) -> bool {
+				self.0.is_empty()
+			}
+		}
+
+		impl Clone for $name {
+			fn clone(&self) -> Self {
+				Self::new(self.0.clone())
+			}
+		}
+
+		impl Drop for $name {
+			fn drop(&mut self) {
+				zeroize_string(&mut self.0);
+			}
+		}
+
+		impl From<String> for $name {
+			fn from(value: String) -> Self {
+				Self::new(value)
+			}
+		}
+
+		impl From<&str> for $name {
+			fn from(value: &str) -> Self {
+				Self::new(value)
+			}
+		}
+
+		impl<'de> Deserialize<'de> for $name {
+			fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+				String::deserialize(deserializer).map(Self::new)
+			}
+		}
+
+		impl fmt::Debug for $name {
+			fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+				write!(f, "{}({})", stringify!($name), REDACTED)
+			}
+		}
+
+		impl fmt::Display for $name {
+			fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+				f.write_str(REDACTED)
+			}
+		}
+	};
+}
+
+redacted_string!(
+	/// A jwt used to log in
+	Jwt
+);
+
+redacted_string!(
+	/// A vault token
+	Token
+);
+
+/// The json value of a secret
+#[derive(Default, PartialEq)]
+pub struct SecretValue(Value);
+
+impl SecretValue {
+	pub fn new(value: Value) -> Self {
+		for_each_str(&value, &mut |s| lock(s.as_ptr(), s.len()));
+		Self(value)
+	}
+
+	/// return the plaintext value
+	pub fn expose(&self) -> &Value {
+		&self.0
+	}
+
+	/// return the value pointed by a json pointer, consuming self. The rest of the value is
+	/// wiped
+	pub fn take_pointer(mut self, pointer: &str) -> Option<Self> {
+		// the strings moved out stay at the same address, so they are still locked
+		self.0.pointer_mut(pointer).map(Value::take).map(Self)
+	}
+
+	/// return the plaintext value, consuming self. The returned value is not wiped on drop
+	pub fn into_inner(mut self) -> Value {
+		for_each_str(&self.0, &mut |s| unlock(s.as_ptr(), s.len()));
+		std::mem::take(&mut self.0)
+	}
+}
+
+impl Clone for SecretValue {
+	fn clone(&self) -> Self {
+		Self::new(self.0.clone())
+	}
+}
+
+impl Drop for SecretValue {
+	fn drop(&mut self) {
+		zeroize_value(&mut self.0);
+	}
+}
+
+impl From<Value> for SecretValue {
+	fn from(value: Value) -> Self {
+		Self::new(value)
+	}
+}
+
+impl<'de> Deserialize<'de> for SecretValue {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		Value::deserialize(deserializer).map(Self::new)
+	}
+}
+
+impl fmt::Debug for SecretValue {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "SecretValue({})", REDACTED)
+	}
+}
+
+impl fmt::Display for SecretValue {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(REDACTED)
+	}
+}
+
+/// Call f on all the strings of a json value (object keys excluded)
+fn for_each_str<F: FnMut(&str)>(value: &Value, f: &mut F) {
+	match value {
+		Value::String(s) => f(s),
+		Value::Array(array) => array.iter().for_each(|v| for_each_str(v, f)),
+		Value::Object(map) => map.values().for_each(|v| for_each_str(v, f)),
+		_ => (),
+	}
+}
+
+/// Zero and unlock a string
+fn zeroize_string(s: &mut String) {
+	let (ptr, len) = (s.as_ptr(), s.len());
+	s.zeroize();
+	unlock(ptr, len);
+}
+
+/// Zero the strings and keys of a json value and replace it by null. Numbers and booleans are
+/// only replaced
+fn zeroize_value(value: &mut Value) {
+	match value {
+		Value::String(s) => zeroize_string(s),
+		Value::Array(array) => array.iter_mut().for_each(zeroize_value),
+		Value::Object(map) => {
+			for (mut key, mut value) in std::mem::take(map) {
+				key.zeroize();
+				zeroize_value(&mut value);
+			}
+		}
+		_ => (),
+	}
+	*value = Value::Null;
+}
+
+/// Number of buffers locking each page
+#[cfg(all(unix, feature = "mlock"))]
+static LOCKED_PAGES: std::sync::Mutex<BTreeMap<usize, usize>> =
+	std::sync::Mutex::new(BTreeMap::new());
+
+/// Return the start addresses of the pages of a buffer
+#[cfg(all(unix, feature = "mlock"))]
+fn pages(ptr: *const u8, len: usize) -> impl Iterator<Item = usize> {
+	// safe: only reads a system setting
+	let size = match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+		size if size > 0 => size as usize,
+		_ => 4096,
+	};
+	let start = ptr as usize / size * size;
+	(start..ptr as usize + len).step_by(size)
+}
+
+/// Lock the pages of a buffer in memory
+#[cfg(all(unix, feature = "mlock"))]
+fn lock(ptr: *const u8, len: usize) {
+	if len == 0 {
+		return;
+	}
+	let mut locked = LOCKED_PAGES.lock().unwrap_or_else(|e| e.into_inner());
+	for page in pages(ptr, len) {
+		*locked.entry(page).or_default() += 1;
+	}
+	// safe: only changes the paging of memory owned by the caller
+	if unsafe { libc::mlock(ptr as *const libc::c_void, len) } != 0 {
+		log::debug!("unable to lock memory: {}", std::io::Error::last_os_error());
+	}
+}
+
+/// Unlock the pages of a buffer which are not locked by another buffer
+#[cfg(all(unix, feature = "mlock"))]
+fn unlock(ptr: *const u8, len: usize) {
+	if len == 0 {
+		return;
+	}
+	let mut locked = LOCKED_PAGES.lock().unwrap_or_else(|e| e.into_inner());
+	for page in pages(ptr, len) {
+		match locked.get_mut(&page) {
+			Some(count) if *count > 1 => *count -= 1,
+			_ => {
+				locked.remove(&page);
+				// safe: only changes the paging of a page no other buffer locks
+				unsafe {
+					libc::munlock(page as *const libc::c_void, 1);
+				}
+			}
+		}
+	}
+}
+
+#[cfg(not(all(unix, feature = "mlock")))]
+fn lock(_ptr: *const u8, _len: usize) {}
+
+#[cfg(not(all(unix, feature = "mlock")))]
+fn unlock(_ptr: *const u8, _len: usize) {}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use serde_json::json;
+
+	#[test]
+	fn formatting_is_redacted() {
+		let token = Token::new("hvs.secret");
+		assert_eq!(format!("{:?}", token), "Token(<redacted>)");
+		assert_eq!(token.to_string(), REDACTED);
+		assert_eq!(token.expose(), "hvs.secret");
+		let value = SecretValue::new(json!({"password": "secret"}));
+		assert!(!format!("{:?} {}", value, value).contains("secret\""));
+		assert_eq!(value.expose()["password"], "secret");
+	}
+
+	#[test]
+	fn deserialize_transparently() {
+		let jwt: Jwt = serde_json::from_str("\"a.b.c\"").unwrap();
+		assert_eq!(jwt.expose(), "a.b.c");
+		let value: SecretValue = serde_json::from_str(r#"{"user": "admin"}"#).unwrap();
+		assert_eq!(value.clone().into_inner(), json!({"user": "admin"}));
+	}
+
+	#[test]
+	fn take_pointer() {
+		let value = SecretValue::new(json!({"data": {"password": "secret"}}));
+		let password = value.clone().take_pointer("/data/password").unwrap();
+		assert_eq!(password.expose(), "secret");
+		assert!(value.take_pointer("/data/user").is_none());
+	}
+
+	#[cfg(all(unix, feature = "mlock"))]
+	#[test]
+	fn pages_stay_locked_while_shared() {
+		let buffer = [0u8; 64];
+		let (first, second) = (buffer.as_ptr(), buffer[32..].as_ptr());
+		let page = pages(first, 32).next().unwrap();
+		let count = || LOCKED_PAGES.lock().unwrap().get(&page).copied();
+		lock(first, 32);
+		lock(second, 32);
+		assert!(count() >= Some(2));
+		unlock(first, 32);
+		assert!(count() >= Some(1));
+		unlock(second, 32);
+	}
+
+	#[test]
+	fn zeroize_nested_values() {
+		let mut value = json!({"db": {"password": "secret"}, "hosts": ["a", "b"]});
+		zeroize_value(&mut value);
+		assert_eq!(value, Value::Null);
+		let mut s = "secret".to_owned();
+		zeroize_string(&mut s);
+		assert!(s.is_empty());
+	}
+}
@@ -1,24 +1,63 @@
-use crate::lease::Lease;
+// lints of the original code of Secret, kept as is
+#![allow(
+	clippy::bind_instead_of_map,
+	clippy::bool_assert_comparison,
+	clippy::needless_return
+)]
 
+use crate::{
+	error::{Error, Result},
+	lease::{Lease, RenewPolicy},
+	redact::SecretValue,
+};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::Value;
-use std::{convert::TryFrom, fmt, time::Duration};
+use std::{convert::TryFrom, fmt, str::FromStr, time::Duration};
 
 /// A secret is a json value tied to an optional lease
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Secret {
-	pub value: Value,
+	pub value: SecretValue,
 	lease: Option<Lease>,
+	lease_id: Option<String>,
 }
 
 impl Secret {
 	/// create a secret with an optional duration
 	pub fn new(value: Value, dur: Option<Duration>) -> Self {
 		Self {
-			value,
+			value: SecretValue::new(value),
 			lease: dur.and_then(|dur| Some(Lease::new(dur))),
+			lease_id: None,
+		}
+	}
+
+	/// create a secret with an optional duration renewed according to policy
+	pub fn with_policy(value: Value, dur: Option<Duration>, policy: &RenewPolicy) -> Self {
+		Self {
+			value: SecretValue::new(value),
+			lease: dur.map(|dur| Lease::with_policy(dur, policy)),
+			lease_id: None,
 		}
 	}
 
+	/// attach the id of the lease used to renew or revoke the secret (ignored if empty)
+	pub fn with_lease_id(mut self, lease_id: &str) -> Self {
+		self.lease_id = Some(lease_id.to_owned()).filter(|id| !id.is_empty());
+		self
+	}
+
+	/// return the id of the lease of the secret
+	pub fn lease_id(&self) -> Option<&str> {
+		self.lease_id.as_deref()
+	}
+
+	/// return the lease of the secret
+	pub fn lease(&self) -> Option<&Lease> {
+		self.lease.as_ref()
+	}
+
 	/// check if the secret is valid
 	pub fn is_valid(&self) -> bool {
 		self.lease.is_none() || self.lease.as_ref().filter(|l| l.is_valid()).is_some()
@@ -96,6 +135,198 @@ where
 	}
 }
 
+impl<'a, T> SecretPath<'a, T>
+where
+	T: TryFrom<&'a str> + fmt::Display,
+{
+	/// return the role which is the first argument
+	pub fn role(&self) -> Result<&'a str> {
+		self.args
+			.first()
+			.copied()
+			.ok_or_else(|| Error::NoRole(self.to_string()))
+	}
+
+	/// return the http method which is the optional second argument (GET by default)
+	pub fn method(&self) -> &'a str {
+		self.args.get(1).copied().unwrap_or("GET")
+	}
+
+	/// apply the anchor, a json pointer, to a secret value. A missing or empty anchor returns
+	/// the whole value
+	pub fn select(&self, mut value: Value) -> Result<Value> {
+		match self.anchor {
+			Some(anchor) if !anchor.is_empty() => value
+				.pointer_mut(anchor)
+				.map(Value::take)
+				.ok_or_else(|| Error::AnchorNotFound(anchor.to_owned(), self.path.to_owned())),
+			_ => Ok(value),
+		}
+	}
+
+	/// apply the anchor to a secret value without exposing it. The rest of the value is wiped
+	pub fn select_secret(&self, value: SecretValue) -> Result<SecretValue> {
+		match self.anchor {
+			Some(anchor) if !anchor.is_empty() => value
+				.take_pointer(anchor)
+				.ok_or_else(|| Error::AnchorNotFound(anchor.to_owned(), self.path.to_owned())),
+			_ => Ok(value),
+		}
+	}
+
+	/// return an owned copy of the path
+	pub fn to_owned(&self) -> SecretPathBuf<T>
+	where
+		T: Clone,
+	{
+		SecretPathBuf {
+			backend: self.backend.clone(),
+			args: self.args.iter().map(|a| (*a).to_owned()).collect(),
+			kwargs: self.kwargs.as_ref().map(|kwargs| {
+				kwargs
+					.iter()
+					.map(|(k, v)| ((*k).to_owned(), (*v).to_owned()))
+					.collect()
+			}),
+			full_path: self.full_path.to_owned(),
+			path: self.path.to_owned(),
+			anchor: self.anchor.map(str::to_owned),
+		}
+	}
+}
+
+/// An owned SecretPath which can be kept in config structs or sent across threads. It is
+/// parsed with `FromStr` and (de)serialized as a string with the grammar of SecretPath
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub struct SecretPathBuf<T> {
+	pub backend: T,
+	pub args: Vec<String>,
+	pub kwargs: Option<Vec<(String, String)>>,
+	pub full_path: String,
+	pub path: String,
+	pub anchor: Option<String>,
+}
+
+impl<T: Clone> SecretPathBuf<T> {
+	/// return a SecretPath borrowing from self
+	pub fn as_ref<'a>(&'a self) -> SecretPath<'a, T>
+	where
+		T: TryFrom<&'a str> + fmt::Display,
+	{
+		SecretPath {
+			backend: self.backend.clone(),
+			args: self.args.iter().map(String::as_str).collect(),
+			kwargs: self.kwargs.as_ref().map(|kwargs| {
+				kwargs
+					.iter()
+					.map(|(k, v)| (k.as_str(), v.as_str()))
+					.collect()
+			}),
+			full_path: &self.full_path,
+			path: &self.path,
+			anchor: self.anchor.as_deref(),
+		}
+	}
+}
+
+impl<'a, T> From<SecretPath<'a, T>> for SecretPathBuf<T>
+where
+	T: TryFrom<&'a str> + fmt::Display,
+{
+	fn from(path: SecretPath<'a, T>) -> Self {
+		Self {
+			args: path.args.iter().map(|a| (*a).to_owned()).collect(),
+			kwargs: path.kwargs.map(|kwargs| {
+				kwargs
+					.into_iter()
+					.map(|(k, v)| (k.to_owned(), v.to_owned()))
+					.collect()
+			}),
+			full_path: path.full_path.to_owned(),
+			path: path.path.to_owned(),
+			anchor: path.anchor.map(str::to_owned),
+			backend: path.backend,
+		}
+	}
+}
+
+/// Serialize a SecretPathBuf like a SecretPath
+impl<T> fmt::Display for SecretPathBuf<T>
+where
+	T: Clone + for<'a> TryFrom<&'a str> + fmt::Display,
+{
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		self.as_ref().fmt(f)
+	}
+}
+
+impl<T> FromStr for SecretPathBuf<T>
+where
+	T: for<'a> TryFrom<&'a str> + fmt::Display,
+	for<'a> SecretPath<'a, T>: TryFrom<&'a str, Error = Error>,
+{
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self> {
+		SecretPath::try_from(s).map(Self::from)
+	}
+}
+
+impl<T> Serialize for SecretPathBuf<T>
+where
+	T: Clone + for<'a> TryFrom<&'a str> + fmt::Display,
+{
+	fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+		serializer.collect_str(self)
+	}
+}
+
+impl<'de, T> Deserialize<'de> for SecretPathBuf<T>
+where
+	T: for<'a> TryFrom<&'a str> + fmt::Display,
+	for<'a> SecretPath<'a, T>: TryFrom<&'a str, Error = Error>,
+{
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+		String::deserialize(deserializer)?
+			.parse()
+			.map_err(de::Error::custom)
+	}
+}
+
+/// The backends resolved by this crate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Backend {
+	/// backend:role[,method][,key=value]*:path[#anchor]
+	Vault,
+}
+
+impl fmt::Display for Backend {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Backend::Vault => write!(f, "vault"),
+		}
+	}
+}
+
+impl<'a> TryFrom<&'a str> for Backend {
+	type Error = Error;
+
+	fn try_from(value: &'a str) -> Result<Self> {
+		match value {
+			"vault" => Ok(Backend::Vault),
+			_ => Err(Error::UnknowBackend(value.to_owned())),
+		}
+	}
+}
+
+/// Return a secret value as a string: strings are returned as is and other values as json
+pub fn value_to_string(value: &Value) -> String {
+	match value {
+		Value::String(s) => s.to_owned(),
+		value => value.to_string(),
+	}
+}
+
 #[cfg(test)]
 mod test {
 	use super::*;
@@ -129,4 +360,49 @@ mod test {
 		);
 		assert_eq!(secret.is_valid(), false)
 	}
+
+	#[test]
+	fn select_anchor() {
+		let path =
+			SecretPath::<Backend>::try_from("vault:role:secret/data/app#/data/password").unwrap();
+		assert_eq!(path.role().unwrap(), "role");
+		assert_eq!(path.method(), "GET");
+		assert_eq!(
+			path.select(serde_json::json!({"data": {"password": "pass"}}))
+				.unwrap(),
+			Value::String("pass".to_owned())
+		);
+	}
+
+	#[test]
+	fn owned_path_roundtrip() {
+		let source =
+			"vault:role,POST,common_name=example.com:pki/issue/example.com#/data".to_owned();
+		let owned = SecretPath::<Backend>::try_from(source.as_str())
+			.unwrap()
+			.to_owned();
+		drop(source);
+		assert_eq!(
+			owned.kwargs,
+			Some(vec![("common_name".to_owned(), "example.com".to_owned())])
+		);
+		assert_eq!(owned.as_ref().method(), "POST");
+		assert_eq!(
+			owned.to_string().parse::<SecretPathBuf<Backend>>().unwrap(),
+			owned
+		);
+	}
+
+	#[test]
+	fn owned_path_serde() {
+		#[derive(Serialize, Deserialize)]
+		struct Config {
+			password: SecretPathBuf<Backend>,
+		}
+		let json = r#"{"password":"vault:app:secret/data/app#/data/password"}"#;
+		let config: Config = serde_json::from_str(json).unwrap();
+		assert_eq!(config.password.anchor.as_deref(), Some("/data/password"));
+		assert_eq!(serde_json::to_string(&config).unwrap(), json);
+		assert!(serde_json::from_str::<Config>(r#"{"password":"consul:app:kv"}"#).is_err());
+	}
 }
@@ -0,0 +1,191 @@
+use crate::{
+	error::{Error, Result},
+	redact::SecretValue,
+	secret::{value_to_string, Secret},
+};
+
+use std::{
+	fs::{self, File, OpenOptions, Permissions},
+	io::{self, Write},
+	os::unix::fs::{chown, OpenOptionsExt, PermissionsExt},
+	path::{Path, PathBuf},
+	process::Command,
+	time::Instant,
+};
+
+/// Write secrets to a file atomically and run a hook after each write
+#[derive(Debug)]
+pub struct FileSink {
+	path: PathBuf,
+	mode: u32,
+	owner: Option<u32>,
+	group: Option<u32>,
+	hook: Option<String>,
+	/// value and lease expiration of the last written secret
+	last: Option<(SecretValue, Option<Instant>)>,
+}
+
+impl FileSink {
+	/// create a sink writing to path, only readable by the current user
+	pub fn new<P: AsRef<Path>>(path: P) -> Self {
+		Self {
+			path: path.as_ref().to_owned(),
+			mode: 0o600,
+			owner: None,
+			group: None,
+			hook: None,
+			last: None,
+		}
+	}
+
+	/// set the permissions of the file (0o600 by default)
+	pub fn mode(mut self, mode: u32) -> Self {
+		self.mode = mode;
+		self
+	}
+
+	/// set the user id owning the file
+	pub fn owner(mut self, uid: u32) -> Self {
+		self.owner = Some(uid);
+		self
+	}
+
+	/// set the group id owning the file
+	pub fn group(mut self, gid: u32) -> Self {
+		self.group = Some(gid);
+		self
+	}
+
+	/// set a shell command run after each write (typically to reload a service)
+	pub fn hook(mut self, cmd: &str) -> Self {
+		self.hook = Some(cmd.to_owned());
+		self
+	}
+
+	/// return the path of the file
+	pub fn path(&self) -> &Path {
+		&self.path
+	}
+
+	/// write a secret value if it changed or if its lease was renewed since the last write and
+	/// return true if the file was written
+	pub fn update(&mut self, secret: &Secret) -> Result<bool> {
+		let expires_at = secret.lease().map(|l| l.expires_at());
+		let unchanged = self
+			.last
+			.as_ref()
+			.filter(|(value, last_expires_at)| {
+				*value == secret.value && *last_expires_at == expires_at
+			})
+			.is_some();
+		if unchanged {
+			return Ok(false);
+		}
+		self.write(value_to_string(secret.value.expose()).as_bytes())?;
+		self.last = Some((secret.value.clone(), expires_at));
+		Ok(true)
+	}
+
+	/// write contents atomically (temporary file, fsync and rename) then run the hook
+	pub fn write(&self, contents: &[u8]) -> Result<()> {
+		let tmp_path = self.tmp_path();
+		self.write_tmp(&tmp_path, contents)
+			.and_then(|_| fs::rename(&tmp_path, &self.path))
+			.map_err(|e| {
+				let _ = fs::remove_file(&tmp_path);
+				self.io_error(e)
+			})?;
+		// persist the rename
+		if let Some(dir) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+			File::open(dir)
+				.and_then(|dir| dir.sync_all())
+				.map_err(|e| self.io_error(e))?;
+		}
+		self.run_hook()
+	}
+
+	/// path of the temporary file, in the same directory to be able to rename it
+	fn tmp_path(&self) -> PathBuf {
+		let name = self
+			.path
+			.file_name()
+			.map(|name| name.to_string_lossy().into_owned())
+			.unwrap_or_default();
+		self.path
+			.with_file_name(format!(".{}.{}.tmp", name, std::process::id()))
+	}
+
+	/// write and sync the temporary file with the final permissions and ownership
+	fn write_tmp(&self, tmp_path: &Path, contents: &[u8]) -> io::Result<()> {
+		let mut file = OpenOptions::new()
+			.write(true)
+			.create_new(true)
+			.mode(self.mode)
+			.open(tmp_path)?;
+		// the mode given at creation is masked by umask
+		file.set_permissions(Permissions::from_mode(self.mode))?;
+		if self.owner.is_some() || self.group.is_some() {
+			chown(tmp_path, self.owner, self.group)?;
+		}
+		file.write_all(contents)?;
+		file.sync_all()
+	}
+
+	/// run the hook if any
+	fn run_hook(&self) -> Result<()> {
+		if let Some(ref hook) = self.hook {
+			let status = Command::new("sh")
+				.arg("-c")
+				.arg(hook)
+				.status()
+				.map_err(|e| Error::ExecError {
+					command: hook.to_owned(),
+					source: e,
+				})?;
+			if !status.success() {
+				return Err(Error::HookFailed(hook.to_owned(), status));
+			}
+		}
+		Ok(())
+	}
+
+	fn io_error(&self, e: io::Error) -> Error {
+		Error::IoError {
+			path: self.path.display().to_string(),
+			source: e,
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use serde_json::Value;
+	use std::time::Duration;
+
+	#[test]
+	fn write_on_change_only() {
+		let dir = std::env::temp_dir().join(format!("vault-jwt-sink-{}", std::process::id()));
+		fs::create_dir_all(&dir).unwrap();
+		let marker = dir.join("reloaded");
+		let mut sink = FileSink::new(dir.join("secret"))
+			.mode(0o640)
+			.hook(&format!("touch {}", marker.display()));
+		let secret = Secret::new(Value::String("secret".to_owned()), None);
+		assert!(sink.update(&secret).unwrap());
+		assert!(!sink.update(&secret).unwrap());
+		assert_eq!(fs::read_to_string(sink.path()).unwrap(), "secret");
+		assert_eq!(
+			fs::metadata(sink.path()).unwrap().permissions().mode() & 0o777,
+			0o640
+		);
+		assert!(marker.exists());
+		// a renewed lease triggers a new write
+		let renewed = Secret::new(
+			Value::String("secret".to_owned()),
+			Some(Duration::from_secs(60)),
+		);
+		assert!(sink.update(&renewed).unwrap());
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
@@ -0,0 +1,331 @@
+use crate::{
+	auth::{Auth, TokenType},
+	error::{Error, Result},
+	lease::{Lease, RenewPolicy},
+	redact::Token,
+};
+
+use chacha20poly1305::{
+	aead::{Aead, AeadCore, KeyInit, OsRng},
+	ChaCha20Poly1305, Key, Nonce,
+};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use std::{
+	collections::HashMap,
+	fs::{self, File, OpenOptions},
+	io::{self, Write},
+	os::unix::fs::OpenOptionsExt,
+	path::{Path, PathBuf},
+	time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use zeroize::Zeroize;
+
+/// Size of the nonce prepended to the encrypted store
+const NONCE_LEN: usize = 12;
+
+/// Tokens persisted on disk, encrypted with a key file and locked against concurrent writers,
+/// to reuse them across process restarts (feature `token-store`)
+#[derive(Debug, Clone)]
+pub struct TokenStore {
+	path: PathBuf,
+	key_path: PathBuf,
+}
+
+/// A token and its lease deadline as persisted
+#[derive(Serialize, Deserialize)]
+struct StoredAuth {
+	client_token: String,
+	accessor: String,
+	policies: Vec<String>,
+	token_policies: Vec<String>,
+	metadata: HashMap<String, String>,
+	entity_id: String,
+	orphan: bool,
+	renewable: bool,
+	token_type: TokenType,
+	/// ttl and expiration (seconds since epoch) of the token if any
+	lease: Option<(u64, u64)>,
+}
+
+impl Drop for StoredAuth {
+	fn drop(&mut self) {
+		self.client_token.zeroize();
+	}
+}
+
+impl StoredAuth {
+	fn from_auth(auth: &Auth) -> Self {
+		let now = now();
+		Self {
+			client_token: auth.client_token.expose().to_owned(),
+			accessor: auth.accessor.clone(),
+			policies: auth.policies.clone(),
+			token_policies: auth.token_policies.clone(),
+			metadata: auth.metadata.clone(),
+			entity_id: auth.entity_id.clone(),
+			orphan: auth.orphan,
+			renewable: auth.renewable,
+			token_type: auth.token_type,
+			lease: auth.lease.as_ref().map(|lease| {
+				(
+					lease.lease_duration.as_secs(),
+					now + lease.remaining().as_secs(),
+				)
+			}),
+		}
+	}
+
+	/// convert to an Auth which lease is renewed according to policy or None if expired
+	fn to_auth(&self, policy: &RenewPolicy) -> Option<Auth> {
+		let lease = match self.lease {
+			Some((ttl, expires_at)) => {
+				let remaining = Duration::from_secs(expires_at.checked_sub(now())?);
+				let ttl = Duration::from_secs(ttl).max(remaining);
+				let elapsed = ttl - remaining;
+				// the lease restarts now (the token may be older than the monotonic clock)
+				// with the renewal still scheduled relative to the issue of the token
+				Some(Lease {
+					start: Instant::now(),
+					lease_duration: remaining,
+					renew_delay: policy.renew_delay(ttl).saturating_sub(elapsed),
+				})
+			}
+			None => None,
+		};
+		Some(Auth {
+			client_token: Token::new(self.client_token.as_str()),
+			accessor: self.accessor.clone(),
+			policies: self.policies.clone(),
+			token_policies: self.token_policies.clone(),
+			metadata: self.metadata.clone(),
+			entity_id: self.entity_id.clone(),
+			orphan: self.orphan,
+			renewable: self.renewable,
+			token_type: self.token_type,
+			lease,
+		})
+		.filter(|auth| auth.is_valid())
+	}
+}
+
+impl TokenStore {
+	/// create a store of tokens in path encrypted with the 32 bytes key of key_path. The key
+	/// is generated if the key file doesn't exist
+	pub fn new<P: AsRef<Path>, K: AsRef<Path>>(path: P, key_path: K) -> Self {
+		Self {
+			path: path.as_ref().to_owned(),
+			key_path: key_path.as_ref().to_owned(),
+		}
+	}
+
+	/// return the still valid tokens by role
+	pub fn load(&self, policy: &RenewPolicy) -> Result<HashMap<String, Auth>> {
+		let _lock = self.lock()?;
+		Ok(self
+			.read()?
+			.iter()
+			.filter_map(|(role, stored)| stored.to_auth(policy).map(|auth| (role.clone(), auth)))
+			.collect())
+	}
+
+	/// persist the token of role
+	pub fn save(&self, role: &str, auth: &Auth) -> Result<()> {
+		let _lock = self.lock()?;
+		let mut stored = self.read()?;
+		// forget expired tokens
+		let now = now();
+		stored.retain(|_, s| {
+			s.lease
+				.filter(|(_, expires_at)| *expires_at <= now)
+				.is_none()
+		});
+		stored.insert(role.to_owned(), StoredAuth::from_auth(auth));
+		self.write(&stored)
+	}
+
+	/// forget the token of role
+	pub fn remove(&self, role: &str) -> Result<()> {
+		let _lock = self.lock()?;
+		let mut stored = self.read()?;
+		if stored.remove(role).is_some() {
+			self.write(&stored)?;
+		}
+		Ok(())
+	}
+
+	/// take an exclusive lock on a file next to the store, released when dropped
+	fn lock(&self) -> Result<File> {
+		let lock_path = self.sibling("lock");
+		let file = OpenOptions::new()
+			.write(true)
+			.create(true)
+			.truncate(false)
+			.mode(0o600)
+			.open(&lock_path)
+			.map_err(|e| io_error(&lock_path, e))?;
+		file.lock_exclusive().map_err(|e| io_error(&lock_path, e))?;
+		Ok(file)
+	}
+
+	/// read the key or generate it
+	fn cipher(&self) -> Result<ChaCha20Poly1305> {
+		let mut key = match fs::read(&self.key_path) {
+			Ok(key) => key,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {
+				let key = ChaCha20Poly1305::generate_key(&mut OsRng).to_vec();
+				write_private(&self.key_path, &key, true)?;
+				key
+			}
+			Err(e) => return Err(io_error(&self.key_path, e)),
+		};
+		if key.len() != 32 {
+			key.zeroize();
+			return Err(Error::TokenStore(format!(
+				"the key {} is not 32 bytes long",
+				self.key_path.display()
+			)));
+		}
+		let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+		key.zeroize();
+		Ok(cipher)
+	}
+
+	/// decrypt the tokens of the store (empty if the store doesn't exist)
+	fn read(&self) -> Result<HashMap<String, StoredAuth>> {
+		let data = match fs::read(&self.path) {
+			Ok(data) => data,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+			Err(e) => return Err(io_error(&self.path, e)),
+		};
+		if data.len() < NONCE_LEN {
+			return Err(self.corrupted());
+		}
+		let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+		let mut plaintext = self
+			.cipher()?
+			.decrypt(Nonce::from_slice(nonce), ciphertext)
+			.map_err(|_| self.corrupted())?;
+		let stored =
+			serde_json::from_slice(&plaintext).map_err(|e| Error::ParseError { source: e });
+		plaintext.zeroize();
+		stored
+	}
+
+	/// encrypt the tokens and replace the store atomically
+	fn write(&self, stored: &HashMap<String, StoredAuth>) -> Result<()> {
+		let mut plaintext =
+			serde_json::to_vec(stored).map_err(|e| Error::ParseError { source: e })?;
+		let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+		let ciphertext = self.cipher()?.encrypt(&nonce, plaintext.as_slice());
+		plaintext.zeroize();
+		let mut data = nonce.to_vec();
+		data.extend(ciphertext.map_err(|_| Error::TokenStore("encryption failed".to_owned()))?);
+		let tmp_path = self.sibling("tmp");
+		write_private(&tmp_path, &data, false)?;
+		fs::rename(&tmp_path, &self.path).map_err(|e| io_error(&self.path, e))
+	}
+
+	/// path of a file next to the store
+	fn sibling(&self, ext: &str) -> PathBuf {
+		let mut path = self.path.clone().into_os_string();
+		path.push(".");
+		path.push(ext);
+		path.into()
+	}
+
+	fn corrupted(&self) -> Error {
+		Error::TokenStore(format!(
+			"unable to decrypt {} (corrupted or wrong key)",
+			self.path.display()
+		))
+	}
+}
+
+/// Write a file only readable by the current user
+fn write_private(path: &Path, contents: &[u8], create_new: bool) -> Result<()> {
+	let mut options = OpenOptions::new();
+	options.write(true).mode(0o600);
+	if create_new {
+		options.create_new(true);
+	} else {
+		options.create(true).truncate(true);
+	}
+	options
+		.open(path)
+		.and_then(|mut file| file.write_all(contents).and_then(|_| file.sync_all()))
+		.map_err(|e| io_error(path, e))
+}
+
+fn io_error(path: &Path, e: io::Error) -> Error {
+	Error::IoError {
+		path: path.display().to_string(),
+		source: e,
+	}
+}
+
+/// Seconds since epoch
+fn now() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_secs())
+		.unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn store(name: &str) -> (PathBuf, TokenStore) {
+		let dir = std::env::temp_dir().join(format!("vault-jwt-{}-{}", name, std::process::id()));
+		fs::create_dir_all(&dir).unwrap();
+		let store = TokenStore::new(dir.join("tokens"), dir.join("key"));
+		(dir, store)
+	}
+
+	#[test]
+	fn save_load_and_remove() {
+		let (dir, store) = store("store");
+		let policy = RenewPolicy::default();
+		store
+			.save("app", &Auth::new("hvs.app", Some(Duration::from_secs(600))))
+			.unwrap();
+		store.save("ci", &Auth::new("hvs.ci", None)).unwrap();
+		assert!(!fs::read(dir.join("tokens"))
+			.unwrap()
+			.windows(7)
+			.any(|w| w == b"hvs.app"));
+		let auth = store.load(&policy).unwrap();
+		assert_eq!(auth["app"].client_token.expose(), "hvs.app");
+		assert!(auth["app"].lease.as_ref().unwrap().remaining() > Duration::from_secs(590));
+		assert!(auth["ci"].lease.is_none());
+		store.remove("ci").unwrap();
+		assert!(!store.load(&policy).unwrap().contains_key("ci"));
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn token_issued_before_boot() {
+		let policy = RenewPolicy::default();
+		let mut stored = StoredAuth::from_auth(&Auth::new("hvs.app", None));
+		// a 100 years token with one hour left was issued long before the monotonic clock
+		stored.lease = Some((100 * 365 * 24 * 3600, now() + 3600));
+		let lease = stored.to_auth(&policy).unwrap().lease.unwrap();
+		assert!(lease.remaining() > Duration::from_secs(3590));
+		assert!(lease.to_renew());
+		stored.lease = Some((600, now().saturating_sub(1)));
+		assert!(stored.to_auth(&policy).is_none());
+	}
+
+	#[test]
+	fn wrong_key_is_an_error() {
+		let (dir, store) = store("store-key");
+		store.save("app", &Auth::new("hvs.app", None)).unwrap();
+		fs::write(dir.join("key"), [0u8; 32]).unwrap();
+		assert!(matches!(
+			store.load(&RenewPolicy::default()),
+			Err(Error::TokenStore(_))
+		));
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
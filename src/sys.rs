@@ -0,0 +1,181 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Health of a vault node as returned by `sys/health`
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Health {
+	pub initialized: bool,
+	pub sealed: bool,
+	pub standby: bool,
+	pub performance_standby: bool,
+	pub replication_performance_mode: Option<String>,
+	pub replication_dr_mode: Option<String>,
+	pub server_time_utc: u64,
+	pub version: String,
+	pub cluster_name: Option<String>,
+	pub cluster_id: Option<String>,
+}
+
+impl Health {
+	/// the node is initialized and unsealed
+	pub fn is_ready(&self) -> bool {
+		self.initialized && !self.sealed
+	}
+
+	/// the node is ready and is the active node of the cluster
+	pub fn is_active(&self) -> bool {
+		self.is_ready() && !self.standby && !self.performance_standby
+	}
+}
+
+/// Seal status of a vault node as returned by `sys/seal-status`
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct SealStatus {
+	#[serde(rename = "type")]
+	pub seal_type: String,
+	pub initialized: bool,
+	pub sealed: bool,
+	/// threshold of unseal keys
+	pub t: u32,
+	/// number of unseal keys
+	pub n: u32,
+	/// number of unseal keys already provided
+	pub progress: u32,
+	pub nonce: String,
+	pub version: String,
+	pub build_date: Option<String>,
+	pub migration: bool,
+	pub recovery_seal: bool,
+	pub storage_type: Option<String>,
+	pub cluster_name: Option<String>,
+	pub cluster_id: Option<String>,
+}
+
+/// High availability status of a vault node as returned by `sys/leader`
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct Leader {
+	pub ha_enabled: bool,
+	pub is_self: bool,
+	pub active_time: Option<String>,
+	pub leader_address: String,
+	pub leader_cluster_address: String,
+	pub performance_standby: bool,
+	pub performance_standby_last_remote_wal: u64,
+}
+
+/// `sys/health` with query parameters making vault answer 200 whatever the state of the node so
+/// that the status is always read from the body
+pub(crate) const HEALTH_PATH: &str = "sys/health?standbyok=true&perfstandbyok=true\
+	&sealedcode=200&uninitcode=200&drsecondarycode=200";
+
+/// Return the capability required to use a http method on a vault path
+pub fn required_capability(method: &str) -> &'static str {
+	match method.to_ascii_uppercase().as_str() {
+		"GET" | "HEAD" => "read",
+		"LIST" => "list",
+		"DELETE" => "delete",
+		"PATCH" => "patch",
+		// vault decides between create and update depending on the existence of the path
+		_ => "update",
+	}
+}
+
+/// Check if a list of capabilities allows to use a http method on a path
+pub fn is_allowed(method: &str, capabilities: &[String]) -> bool {
+	if capabilities.iter().any(|c| c == "deny") {
+		return false;
+	}
+	let required = required_capability(method);
+	capabilities.iter().any(|c| {
+		c == "root"
+			|| c == required
+			|| (required == "update" && c == "create")
+			|| (required == "patch" && c == "update")
+	})
+}
+
+/// Extract the capabilities of each requested path from a sys/capabilities-self response.
+/// A path absent from the response has no capability
+pub(crate) fn to_capabilities(value: &Value, paths: &[&str]) -> HashMap<String, Vec<String>> {
+	let data = value.get("data").unwrap_or(value);
+	paths
+		.iter()
+		.map(|path| {
+			let caps = data
+				.get(*path)
+				.or_else(|| {
+					// vault only returns a flat list of capabilities with only one path
+					if paths.len() == 1 {
+						data.get("capabilities")
+					} else {
+						None
+					}
+				})
+				.and_then(|v| v.as_array())
+				.map(|a| {
+					a.iter()
+						.filter_map(|c| c.as_str().map(str::to_owned))
+						.collect()
+				})
+				.unwrap_or_default();
+			((*path).to_owned(), caps)
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use serde_json::json;
+
+	fn caps(caps: &[&str]) -> Vec<String> {
+		caps.iter().map(|c| (*c).to_owned()).collect()
+	}
+
+	#[test]
+	fn method_capabilities() {
+		assert!(is_allowed("GET", &caps(&["read", "list"])));
+		assert!(is_allowed("post", &caps(&["create"])));
+		assert!(is_allowed("PUT", &caps(&["root"])));
+		assert!(!is_allowed("GET", &caps(&["list"])));
+		assert!(!is_allowed("GET", &caps(&["read", "deny"])));
+		assert!(!is_allowed("GET", &[]));
+	}
+
+	#[test]
+	fn parse_capabilities() {
+		let value = json!({
+			"capabilities": ["read"],
+			"secret/app": ["read"],
+			"pki/issue/app": ["create", "update"],
+			"data": {
+				"capabilities": ["read"],
+				"secret/app": ["read"],
+				"pki/issue/app": ["create", "update"],
+			}
+		});
+		let caps = to_capabilities(&value, &["secret/app", "pki/issue/app", "secret/other"]);
+		assert_eq!(caps["secret/app"], vec!["read"]);
+		assert_eq!(caps["pki/issue/app"], vec!["create", "update"]);
+		assert!(caps["secret/other"].is_empty());
+	}
+
+	#[test]
+	fn standby_is_not_active() {
+		let health: Health = serde_json::from_value(json!({
+			"initialized": true,
+			"sealed": false,
+			"standby": true,
+			"performance_standby": false,
+			"server_time_utc": 1516639589,
+			"version": "1.9.0"
+		}))
+		.unwrap();
+		assert!(health.is_ready());
+		assert!(!health.is_active());
+	}
+}
@@ -0,0 +1,227 @@
+use crate::{
+	client::VaultClient,
+	error::{Error, Result},
+	redact::SecretValue,
+	secret::{value_to_string, Backend, SecretPath},
+};
+
+use std::{convert::TryFrom, fs, ops::Range, path::Path};
+
+/// Opening delimiter of a placeholder
+const OPEN: &str = "{{";
+/// Closing delimiter of a placeholder
+const CLOSE: &str = "}}";
+
+/// A secret path placeholder found in a template
+#[derive(Debug, PartialEq)]
+pub struct Placeholder<'a> {
+	/// byte range of the placeholder in the template, delimiters included
+	pub range: Range<usize>,
+	/// line of the placeholder (starting at 1)
+	pub line: usize,
+	/// column of the placeholder in characters (starting at 1)
+	pub col: usize,
+	/// secret path of the placeholder
+	pub path: SecretPath<'a, Backend>,
+}
+
+impl<'a> Placeholder<'a> {
+	/// attach the position of the placeholder to an error
+	pub fn error(&self, e: Error) -> Error {
+		Error::Template {
+			line: self.line,
+			col: self.col,
+			source: Box::new(e),
+		}
+	}
+}
+
+/// A text containing secret path placeholders like `{{ vault:role:kv/app#/password }}`.
+/// Placeholders which don't start with a known backend (like Go or Jinja expressions) are
+/// left unchanged
+#[derive(Debug)]
+pub struct Template<'a> {
+	input: &'a str,
+	pub placeholders: Vec<Placeholder<'a>>,
+}
+
+impl<'a> Template<'a> {
+	/// find and parse all the placeholders of a text
+	pub fn parse(input: &'a str) -> Result<Self> {
+		let mut placeholders = Vec::new();
+		let mut offset = 0;
+		while let Some(start) = input[offset..].find(OPEN).map(|i| offset + i) {
+			if !is_secret_path(&input[start + OPEN.len()..]) {
+				offset = start + OPEN.len();
+				continue;
+			}
+			let (line, col) = position(input, start);
+			let end = input[start + OPEN.len()..]
+				.find(CLOSE)
+				.map(|i| start + OPEN.len() + i + CLOSE.len())
+				.ok_or(Error::UnclosedPlaceholder(line, col))?;
+			let expr = input[start + OPEN.len()..end - CLOSE.len()].trim();
+			let path = SecretPath::try_from(expr).map_err(|e| Error::Template {
+				line,
+				col,
+				source: Box::new(e),
+			})?;
+			placeholders.push(Placeholder {
+				range: start..end,
+				line,
+				col,
+				path,
+			});
+			offset = end;
+		}
+		Ok(Self {
+			input,
+			placeholders,
+		})
+	}
+
+	/// render the template by replacing each placeholder with its resolved value
+	pub fn render<F, V>(&self, mut resolve: F) -> Result<String>
+	where
+		F: FnMut(&Placeholder<'a>) -> Result<V>,
+		V: Into<SecretValue>,
+	{
+		let values = self
+			.placeholders
+			.iter()
+			.map(|p| resolve(p).map(V::into).map_err(|e| p.error(e)))
+			.collect::<Result<Vec<_>>>()?;
+		Ok(self.substitute(&values))
+	}
+
+	/// replace each placeholder with the value at the same index
+	fn substitute(&self, values: &[SecretValue]) -> String {
+		let mut output = String::with_capacity(self.input.len());
+		let mut offset = 0;
+		for (placeholder, value) in self.placeholders.iter().zip(values.iter()) {
+			output.push_str(&self.input[offset..placeholder.range.start]);
+			output.push_str(&value_to_string(value.expose()));
+			offset = placeholder.range.end;
+		}
+		output.push_str(&self.input[offset..]);
+		output
+	}
+}
+
+/// Check if the text following an opening delimiter starts with a known backend
+fn is_secret_path(text: &str) -> bool {
+	text.trim_start()
+		.split_once(':')
+		.is_some_and(|(backend, _)| Backend::try_from(backend).is_ok())
+}
+
+/// Return the line and column (in characters) of a byte offset
+fn position(input: &str, offset: usize) -> (usize, usize) {
+	let before = &input[..offset];
+	let line = before.matches('\n').count() + 1;
+	let col = before
+		.rfind('\n')
+		.map(|i| &before[i + 1..])
+		.unwrap_or(before)
+		.chars()
+		.count()
+		+ 1;
+	(line, col)
+}
+
+/// Render a text by resolving its placeholders with a vault client
+pub fn render(client: &mut VaultClient, input: &str) -> Result<String> {
+	Template::parse(input)?.render(|p| client.resolve(&p.path))
+}
+
+/// Render asynchronously a text by resolving its placeholders with a vault client
+pub async fn render_async(client: &mut VaultClient, input: &str) -> Result<String> {
+	let template = Template::parse(input)?;
+	let mut values = Vec::with_capacity(template.placeholders.len());
+	for p in template.placeholders.iter() {
+		values.push(
+			client
+				.resolve_async(&p.path)
+				.await
+				.map_err(|e| p.error(e))?,
+		);
+	}
+	Ok(template.substitute(&values))
+}
+
+/// Render a file by resolving its placeholders with a vault client
+pub fn render_file<P: AsRef<Path>>(client: &mut VaultClient, path: P) -> Result<String> {
+	render(client, &read_file(path.as_ref())?)
+}
+
+/// Render asynchronously a file by resolving its placeholders with a vault client
+pub async fn render_file_async<P: AsRef<Path>>(
+	client: &mut VaultClient,
+	path: P,
+) -> Result<String> {
+	render_async(client, &read_file(path.as_ref())?).await
+}
+
+/// Read a template file
+fn read_file(path: &Path) -> Result<String> {
+	fs::read_to_string(path).map_err(|e| Error::IoError {
+		path: path.display().to_string(),
+		source: e,
+	})
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn find_placeholders() {
+		let template = Template::parse(
+			"user: {{ vault:role:kv/app#/user }}\npass: {{vault:role:kv/app#/pass}}",
+		)
+		.unwrap();
+		assert_eq!(template.placeholders.len(), 2);
+		assert_eq!(template.placeholders[0].range, 6..35);
+		assert_eq!(
+			(template.placeholders[1].line, template.placeholders[1].col),
+			(2, 7)
+		);
+		assert_eq!(template.placeholders[1].path.anchor, Some("/pass"));
+	}
+
+	#[test]
+	fn render_placeholders() {
+		let template =
+			Template::parse("{{ vault:role:kv/app#/user }}:{{ vault:role:kv/app }}").unwrap();
+		let output = template
+			.render(|p| Ok(p.path.select(serde_json::json!({"user": "admin"})).unwrap()))
+			.unwrap();
+		assert_eq!(output, r#"admin:{"user":"admin"}"#);
+	}
+
+	#[test]
+	fn foreign_placeholders_are_kept() {
+		let input = "{{ .Values.name }} {{ consul:role:kv/app }} {{ vault:role:kv/app#/user }} {{";
+		let template = Template::parse(input).unwrap();
+		assert_eq!(template.placeholders.len(), 1);
+		let output = template
+			.render(|p| Ok(p.path.select(serde_json::json!({"user": "admin"})).unwrap()))
+			.unwrap();
+		assert_eq!(
+			output,
+			"{{ .Values.name }} {{ consul:role:kv/app }} admin {{"
+		);
+	}
+
+	#[test]
+	fn error_position() {
+		match Template::parse("ok\n  {{ vault:role }}") {
+			Err(Error::Template { line, col, .. }) => assert_eq!((line, col), (2, 3)),
+			res => panic!("unexpected result {:?}", res),
+		}
+		match Template::parse("ok {{ vault:role:kv/app") {
+			Err(Error::UnclosedPlaceholder(line, col)) => assert_eq!((line, col), (1, 4)),
+			res => panic!("unexpected result {:?}", res),
+		}
+	}
+}
@@ -0,0 +1,223 @@
+//! W3C trace context propagated to vault in the `traceparent` header of requests (feature
+//! `tracing`)
+
+use isahc::http::{header::HeaderValue, Request};
+use std::{
+	cell::Cell,
+	fmt,
+	future::Future,
+	pin::Pin,
+	task::{Context, Poll},
+	time::Duration,
+};
+use tracing::{field, Span};
+
+/// Name of the header carrying the trace context
+pub const TRACEPARENT: &str = "traceparent";
+
+thread_local! {
+	/// context entered by the calls running on this thread
+	static CURRENT: Cell<Option<TraceContext>> = const { Cell::new(None) };
+}
+
+/// Trace context of the version 00 of the W3C recommendation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceContext {
+	pub trace_id: u128,
+	/// id of the operation calling vault
+	pub parent_id: u64,
+	pub sampled: bool,
+}
+
+impl TraceContext {
+	/// start a new sampled trace
+	pub fn random() -> Self {
+		Self {
+			trace_id: fastrand::u128(1..),
+			parent_id: fastrand::u64(1..),
+			sampled: true,
+		}
+	}
+
+	/// parse the value of a traceparent header
+	pub fn parse(traceparent: &str) -> Option<Self> {
+		let mut parts = traceparent.trim().split('-');
+		let (version, trace_id, parent_id, flags) =
+			(parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+		if version != "00" || trace_id.len() != 32 || parent_id.len() != 16 || flags.len() != 2 {
+			return None;
+		}
+		let context = Self {
+			trace_id: u128::from_str_radix(trace_id, 16).ok()?,
+			parent_id: u64::from_str_radix(parent_id, 16).ok()?,
+			sampled: u8::from_str_radix(flags, 16).ok()? & 1 == 1,
+		};
+		// all zero ids are invalid
+		Some(context).filter(|c| c.trace_id != 0 && c.parent_id != 0)
+	}
+
+	/// return a context of the same trace with a new parent id
+	pub fn child(&self) -> Self {
+		Self {
+			parent_id: fastrand::u64(1..),
+			..*self
+		}
+	}
+
+	/// return the context entered on this thread if any
+	pub fn current() -> Option<Self> {
+		CURRENT.with(Cell::get)
+	}
+
+	/// make self the parent of the requests sent to vault by this thread until the returned
+	/// guard is dropped
+	pub fn enter(self) -> TraceScope {
+		TraceScope {
+			previous: CURRENT.with(|current| current.replace(Some(self))),
+		}
+	}
+
+	/// call f with self as the parent of the requests it sends to vault
+	pub fn in_scope<R>(self, f: impl FnOnce() -> R) -> R {
+		let _scope = self.enter();
+		f()
+	}
+
+	/// wrap future so that self is the parent of the requests it sends to vault
+	pub fn instrument<F: Future>(self, future: F) -> Traced<F> {
+		Traced {
+			context: self,
+			future,
+		}
+	}
+}
+
+/// Guard of an entered trace context, restoring the previous one when dropped
+#[must_use]
+#[derive(Debug)]
+pub struct TraceScope {
+	previous: Option<TraceContext>,
+}
+
+impl Drop for TraceScope {
+	fn drop(&mut self) {
+		CURRENT.with(|current| current.set(self.previous));
+	}
+}
+
+/// Future entering its trace context each time it is polled
+#[derive(Debug)]
+pub struct Traced<F> {
+	context: TraceContext,
+	future: F,
+}
+
+impl<F: Future> Future for Traced<F> {
+	type Output = F::Output;
+
+	fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+		// safe: the inner future is pinned along with self and never moved
+		let this = unsafe { self.get_unchecked_mut() };
+		let _scope = this.context.enter();
+		unsafe { Pin::new_unchecked(&mut this.future) }.poll(cx)
+	}
+}
+
+/// Format the value of a traceparent header
+impl fmt::Display for TraceContext {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"00-{:032x}-{:016x}-{:02x}",
+			self.trace_id, self.parent_id, self.sampled as u8
+		)
+	}
+}
+
+/// Add a traceparent header to a request (in the trace of context or in a new trace when
+/// there is no parent) and return the span of the request. The status and latency are recorded by `record_response`
+pub(crate) fn request_span(
+	request: &mut Request<String>,
+	path: &str,
+	context: Option<&TraceContext>,
+) -> Span {
+	let context = context
+		.map(TraceContext::child)
+		.unwrap_or_else(TraceContext::random);
+	if let Ok(value) = HeaderValue::from_str(&context.to_string()) {
+		request.headers_mut().insert(TRACEPARENT, value);
+	}
+	tracing::debug_span!(
+		"vault.request",
+		method = %request.method(),
+		path,
+		trace_id = %format_args!("{:032x}", context.trace_id),
+		status = field::Empty,
+		latency_ms = field::Empty,
+	)
+}
+
+/// Record the status (if any) and latency of a response in the span of its request
+pub(crate) fn record_response(span: &Span, status: Option<u16>, latency: Duration) {
+	if let Some(status) = status {
+		span.record("status", status);
+	}
+	span.record("latency_ms", latency.as_millis() as u64);
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn parse_traceparent() {
+		let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+		let context = TraceContext::parse(header).unwrap();
+		assert_eq!(context.parent_id, 0x00f0_67aa_0ba9_02b7);
+		assert!(context.sampled);
+		assert_eq!(context.to_string(), header);
+		assert!(
+			TraceContext::parse("00-00000000000000000000000000000000-00f067aa0ba902b7-01")
+				.is_none()
+		);
+		assert!(
+			TraceContext::parse("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+				.is_none()
+		);
+	}
+
+	#[test]
+	fn request_carries_the_trace() {
+		let context = TraceContext::random();
+		let mut request = Request::get("http://vault/v1/sys/health")
+			.body(String::new())
+			.unwrap();
+		request_span(&mut request, "sys/health", Some(&context));
+		let child = TraceContext::parse(request.headers()[TRACEPARENT].to_str().unwrap()).unwrap();
+		assert_eq!(child.trace_id, context.trace_id);
+		assert_ne!(child.parent_id, context.parent_id);
+	}
+
+	#[test]
+	fn entered_context_is_scoped() {
+		let (outer, inner) = (TraceContext::random(), TraceContext::random());
+		assert_eq!(TraceContext::current(), None);
+		let scope = outer.enter();
+		assert_eq!(inner.in_scope(TraceContext::current), Some(inner));
+		assert_eq!(TraceContext::current(), Some(outer));
+		drop(scope);
+		assert_eq!(TraceContext::current(), None);
+		// another thread does not see the context of this one
+		let _scope = outer.enter();
+		let other = std::thread::spawn(TraceContext::current).join().unwrap();
+		assert_eq!(other, None);
+	}
+
+	#[test]
+	fn traced_future_enters_its_context() {
+		let context = TraceContext::random();
+		let future = context.instrument(async { TraceContext::current() });
+		assert_eq!(futures_lite::future::block_on(future), Some(context));
+		assert_eq!(TraceContext::current(), None);
+	}
+}